@@ -0,0 +1,251 @@
+//! 使用 btrfs 命令行 (btrfs-progs)
+use std::{
+    cell::Cell,
+    fs::read_dir,
+    io,
+    os::unix::fs::MetadataExt,
+    path::{Path, PathBuf},
+    process::Command,
+    str::FromStr,
+};
+
+use log::{debug, warn};
+
+use crate::{
+    cli::{sh_output, sh_run},
+    error::{PmbsError, PmbsResult},
+};
+
+use super::{SnapshotBackend, SubvolInfo};
+
+/// btrfs subvol 根目录的 inode 编号 (BTRFS_FIRST_FREE_OBJECTID)
+const SUBVOL_INO: u64 = 256;
+
+/// 解析 `btrfs subvol show` 的输出
+pub fn parse_subvol_show(text: &str) -> Option<SubvolInfo> {
+    let mut uuid: Option<String> = None;
+    let mut parent_uuid: Option<String> = None;
+    let mut generation: Option<u64> = None;
+    let mut gen_at_creation: Option<u64> = None;
+    let mut readonly = false;
+
+    for line in text.lines() {
+        if let Some((k, v)) = line.split_once(':') {
+            let v = v.trim();
+            match k.trim() {
+                "UUID" => {
+                    uuid = Some(v.to_string());
+                }
+                // 没有时显示为 `-`
+                "Parent UUID" if v != "-" => {
+                    parent_uuid = Some(v.to_string());
+                }
+                "Generation" => {
+                    generation = FromStr::from_str(v).ok();
+                }
+                "Gen at creation" => {
+                    gen_at_creation = FromStr::from_str(v).ok();
+                }
+                "Flags" => {
+                    readonly = v.split_whitespace().any(|x| x == "readonly");
+                }
+                _ => {}
+            }
+        }
+    }
+    Some(SubvolInfo {
+        uuid: uuid?,
+        parent_uuid,
+        generation: generation?,
+        gen_at_creation: gen_at_creation?,
+        readonly,
+    })
+}
+
+/// btrfs 命令行
+#[derive(Debug, Clone)]
+pub struct BtrfsBackend {
+    /// btrfs 命令
+    bin: String,
+    /// ionice 命令 (空字符串表示不使用)
+    ionice: String,
+    /// ionice 命令不存在 (之后不再使用)
+    no_ionice: Cell<bool>,
+}
+
+impl BtrfsBackend {
+    pub fn new(bin: &str, ionice: &str) -> Self {
+        Self {
+            bin: bin.into(),
+            ionice: ionice.into(),
+            no_ionice: Cell::new(false),
+        }
+    }
+
+    fn cmd(&self) -> Command {
+        let mut c = Command::new(&self.bin);
+        c.arg("subvol");
+        c
+    }
+
+    /// 使用空闲 IO 优先级执行, 比如 `ionice -c 3 btrfs subvol`
+    fn cmd_idle(&self) -> Command {
+        if self.ionice.is_empty() {
+            return self.cmd();
+        }
+        let mut c = Command::new(&self.ionice);
+        c.arg("-c").arg("3").arg(&self.bin).arg("subvol");
+        c
+    }
+
+    /// 使用空闲 IO 优先级执行命令, `args`: 添加命令参数
+    ///
+    /// ionice 命令不存在时 (比如精简的系统), 输出警告, 直接执行 btrfs 命令.
+    fn run_idle(&self, args: impl Fn(&mut Command)) -> PmbsResult<()> {
+        if !self.ionice.is_empty() && !self.no_ionice.get() {
+            let mut c = self.cmd_idle();
+            args(&mut c);
+            match sh_run(c) {
+                Err(PmbsError::Io(_, e)) if e.kind() == io::ErrorKind::NotFound => {
+                    warn!("ionice not found, run without  {}", self.ionice);
+                    self.no_ionice.set(true);
+                }
+                r => {
+                    return r;
+                }
+            }
+        }
+        let mut c = self.cmd();
+        args(&mut c);
+        sh_run(c)
+    }
+}
+
+impl SnapshotBackend for BtrfsBackend {
+    fn snapshot(&self, subvol: &Path, to: &Path) -> PmbsResult<()> {
+        // 执行命令, 比如 btrfs subvol snapshot -r /home /home/.pmbs/2025/1756392923
+        let mut c = self.cmd();
+        c.arg("snapshot").arg("-r").arg(subvol).arg(to);
+        sh_run(c)
+    }
+
+    fn delete(&self, path: &Path) -> PmbsResult<()> {
+        // 执行命令, 比如 ionice -c 3 btrfs subvol delete /home/.pmbs/2025/1756392923
+        self.run_idle(|c| {
+            c.arg("delete").arg(path);
+        })
+    }
+
+    fn delete_batch(&self, list: &[PathBuf]) -> PmbsResult<()> {
+        self.run_idle(|c| {
+            c.arg("delete").args(list);
+        })
+    }
+
+    fn show(&self, path: &Path) -> PmbsResult<SubvolInfo> {
+        // 执行命令, 比如 btrfs subvol show /home
+        let mut c = self.cmd();
+        c.arg("show").arg(path);
+        let i = parse_subvol_show(&sh_output(c)?);
+
+        debug!("subvol show {}  {:?}", path.to_string_lossy(), i);
+        i.ok_or_else(|| {
+            PmbsError::io(path)(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "can not parse btrfs subvol show",
+            ))
+        })
+    }
+
+    fn list(&self, dir: &Path) -> PmbsResult<Vec<PathBuf>> {
+        // subvol 根目录的 inode 编号总是 256
+        let mut o = Vec::new();
+        for i in read_dir(dir).map_err(PmbsError::io(dir))? {
+            let p = i.map_err(PmbsError::io(dir))?.path();
+            let m = p.symlink_metadata().map_err(PmbsError::io(&p))?;
+            if m.is_dir() && m.ino() == SUBVOL_INO {
+                o.push(p);
+            }
+        }
+        Ok(o)
+    }
+
+    fn sync(&self, path: &Path) -> PmbsResult<()> {
+        // 执行命令, 比如 btrfs subvol sync /home/.pmbs
+        let mut c = self.cmd();
+        c.arg("sync").arg(path);
+        sh_run(c)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn parse_show() {
+        let text = r#"home/.pmbs/2025/1756392923
+	Name: 			1756392923
+	UUID: 			0b5c4d8e-2c5f-4f4e-9e0a-7d1f3b4c5a6b
+	Parent UUID: 		3a6e9f2d-8b7c-4d1e-a5f0-1c2b3d4e5f60
+	Received UUID: 		-
+	Creation time: 		2025-08-28 23:35:23 +0800
+	Subvolume ID: 		1234
+	Generation: 		56789
+	Gen at creation: 	56788
+	Parent ID: 		256
+	Top level ID: 		256
+	Flags: 			readonly
+"#;
+        assert_eq!(
+            parse_subvol_show(text),
+            Some(SubvolInfo {
+                uuid: "0b5c4d8e-2c5f-4f4e-9e0a-7d1f3b4c5a6b".into(),
+                parent_uuid: Some("3a6e9f2d-8b7c-4d1e-a5f0-1c2b3d4e5f60".into()),
+                generation: 56789,
+                gen_at_creation: 56788,
+                readonly: true,
+            })
+        );
+
+        let text = r#"home
+	Name: 			home
+	UUID: 			3a6e9f2d-8b7c-4d1e-a5f0-1c2b3d4e5f60
+	Parent UUID: 		-
+	Generation: 		56790
+	Gen at creation: 	6
+	Flags: 			-
+"#;
+        let i = parse_subvol_show(text).unwrap();
+        assert_eq!(i.parent_uuid, None);
+        assert!(!i.readonly);
+
+        assert_eq!(parse_subvol_show(""), None);
+        assert_eq!(parse_subvol_show("\tGeneration: \t\t1\n"), None);
+    }
+
+    #[test]
+    fn cmd_idle() {
+        let args = |c: Command| -> Vec<String> {
+            let mut o = vec![c.get_program().to_string_lossy().to_string()];
+            o.extend(c.get_args().map(|x| x.to_string_lossy().to_string()));
+            o
+        };
+        let b = BtrfsBackend::new("btrfs", "ionice");
+        assert_eq!(args(b.cmd_idle()), ["ionice", "-c", "3", "btrfs", "subvol"]);
+        // 不使用 ionice
+        let b = BtrfsBackend::new("btrfs", "");
+        assert_eq!(args(b.cmd_idle()), ["btrfs", "subvol"]);
+
+        // ionice 命令不存在: 直接执行
+        let b = BtrfsBackend::new("true", "/nonexistent/ionice");
+        b.delete(Path::new("/nonexistent")).unwrap();
+        assert!(b.no_ionice.get());
+        // btrfs 命令失败, 不是 ionice 的问题
+        let b = BtrfsBackend::new("false", "/nonexistent/ionice");
+        assert!(matches!(
+            b.delete(Path::new("/nonexistent")),
+            Err(PmbsError::Command(..))
+        ));
+    }
+}
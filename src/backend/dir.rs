@@ -0,0 +1,216 @@
+//! 使用普通目录 (复制文件) 模拟快照, 不需要 btrfs 文件系统
+use std::{
+    fs::{self, File, Metadata, copy, create_dir, read_dir, read_link, remove_dir_all},
+    io,
+    os::unix::fs::symlink,
+    path::{Path, PathBuf},
+    time::SystemTime,
+};
+
+use log::debug;
+
+use crate::{
+    error::{PmbsError, PmbsResult},
+    snapshot::DIR_PMBS,
+};
+
+use super::{SnapshotBackend, SubvolInfo};
+
+/// 普通目录
+#[derive(Debug, Clone, Default)]
+pub struct DirBackend {}
+
+impl DirBackend {
+    pub fn new() -> Self {
+        Self {}
+    }
+}
+
+/// 修改时间 (UNIX_EPOCH 开始的纳秒数)
+fn mtime_ns(m: &Metadata) -> u64 {
+    m.modified()
+        .ok()
+        .and_then(|t| t.duration_since(SystemTime::UNIX_EPOCH).ok())
+        .map(|d| d.as_nanos() as u64)
+        .unwrap_or(0)
+}
+
+/// 递归复制目录 (顶层的 .pmbs 除外)
+fn copy_dir(from: &Path, to: &Path, top: bool) -> io::Result<()> {
+    create_dir(to)?;
+    for i in read_dir(from)? {
+        let i = i?;
+        if top && i.file_name() == DIR_PMBS {
+            continue;
+        }
+        let f = i.path();
+        let t = to.join(i.file_name());
+        let m = f.symlink_metadata()?;
+        if m.is_symlink() {
+            symlink(read_link(&f)?, &t)?;
+        } else if m.is_dir() {
+            copy_dir(&f, &t, false)?;
+        } else {
+            copy(&f, &t)?;
+            // 和 btrfs 快照一样, 保留修改时间
+            File::open(&t)?.set_modified(m.modified()?)?;
+        }
+    }
+    Ok(())
+}
+
+/// 目录树中最新的修改时间 (顶层的 .pmbs 除外)
+fn max_mtime(p: &Path, top: bool) -> io::Result<u64> {
+    let m = p.symlink_metadata()?;
+    let mut t = mtime_ns(&m);
+    if m.is_dir() {
+        for i in read_dir(p)? {
+            let i = i?;
+            if top && i.file_name() == DIR_PMBS {
+                continue;
+            }
+            t = t.max(max_mtime(&i.path(), false)?);
+        }
+    }
+    Ok(t)
+}
+
+impl SnapshotBackend for DirBackend {
+    fn snapshot(&self, subvol: &Path, to: &Path) -> PmbsResult<()> {
+        debug!(
+            "copy dir {} -> {}",
+            subvol.to_string_lossy(),
+            to.to_string_lossy()
+        );
+        copy_dir(subvol, to, true).map_err(PmbsError::io(to))
+    }
+
+    fn delete(&self, path: &Path) -> PmbsResult<()> {
+        debug!("rm dir {}", path.to_string_lossy());
+        remove_dir_all(path).map_err(PmbsError::io(path))
+    }
+
+    fn show(&self, path: &Path) -> PmbsResult<SubvolInfo> {
+        // 用修改时间模拟 generation
+        let m = fs::metadata(path).map_err(PmbsError::io(path))?;
+        Ok(SubvolInfo {
+            uuid: String::new(),
+            parent_uuid: None,
+            generation: max_mtime(path, true).map_err(PmbsError::io(path))?,
+            gen_at_creation: mtime_ns(&m),
+            readonly: m.permissions().readonly(),
+        })
+    }
+
+    fn list(&self, dir: &Path) -> PmbsResult<Vec<PathBuf>> {
+        let mut o = Vec::new();
+        for i in read_dir(dir).map_err(PmbsError::io(dir))? {
+            let p = i.map_err(PmbsError::io(dir))?.path();
+            if p.symlink_metadata().map_err(PmbsError::io(&p))?.is_dir() {
+                o.push(p);
+            }
+        }
+        Ok(o)
+    }
+
+    fn sync(&self, _path: &Path) -> PmbsResult<()> {
+        // 删除目录立即释放存储空间
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::fs::write;
+
+    use super::*;
+    use crate::{
+        clean::{RmOpt, RmStatus, decide, ls_snapshot, safe_rm_subvol_list},
+        config::PmbsConfig,
+        error::PmbsError,
+        snapshot::{DIR_META, Trigger, make_snapshot},
+        test_util::TmpDir,
+    };
+
+    /// 使用普通目录, 测试 创建快照 -> 列出快照 -> 清理快照
+    #[test]
+    fn snapshot_clean() {
+        let tmp = TmpDir::new("dir");
+        let subvol = tmp.join("subvol");
+        fs::create_dir_all(subvol.join("a")).unwrap();
+        write(subvol.join("a/b.txt"), "666").unwrap();
+
+        let c = tmp.env();
+        let subvol = subvol.to_string_lossy().to_string();
+        // 快照保存在 subvol 之外
+        let mut config = PmbsConfig::new_subvol(&subvol);
+        config.snapshot_dir = Some(tmp.join(".pmbs-store/home").to_string_lossy().to_string());
+        let dir = config.dir_pmbs();
+        make_snapshot(&c, &config, &Default::default()).unwrap();
+        assert!(!PathBuf::from(&subvol).join(DIR_PMBS).exists());
+
+        let list = ls_snapshot(&dir).unwrap();
+        assert_eq!(list.len(), 1);
+        assert!(list[0].latest);
+        let copy = list[0].p.join("a/b.txt");
+        assert_eq!(fs::read_to_string(copy).unwrap(), "666");
+        // 快照中不包含 .pmbs
+        assert!(!list[0].p.join(DIR_PMBS).exists());
+        // 快照元数据
+        let meta = list[0].meta.clone().unwrap();
+        assert_eq!(meta.trigger, Trigger::Timer);
+        assert_eq!(meta.version, env!("CARGO_PKG_VERSION"));
+
+        // 没有保留规则, 但是最新的快照总是保留
+        let d = decide(&config, list.clone());
+        assert_eq!((d.keep.len(), d.clean.len()), (1, 0));
+
+        let p = list[0].p.to_string_lossy().to_string();
+        // 不在快照保存目录之中, 拒绝删除
+        let bad = PathBuf::from(&subvol).join(".pmbs/2025/1756392923");
+        assert!(matches!(
+            safe_rm_subvol_list(
+                &c,
+                &config,
+                vec![bad.to_string_lossy().to_string()],
+                &RmOpt::default()
+            ),
+            Err(PmbsError::Safety(_))
+        ));
+        // 分批删除: 再加上 2 个快照, 以及 1 个已经不存在的快照
+        let mut list = vec![p];
+        for t in [1756392923, 1756392924] {
+            let s = dir.join(format!("2025/{}", t));
+            fs::create_dir_all(&s).unwrap();
+            list.push(s.to_string_lossy().to_string());
+        }
+        list.push(dir.join("2025/1756392925").to_string_lossy().to_string());
+        let opt = RmOpt {
+            batch_size: 2,
+            sync: true,
+        };
+        let r = safe_rm_subvol_list(&c, &config, list, &opt).unwrap();
+        let status: Vec<RmStatus> = r.iter().map(|x| x.1.status).collect();
+        assert_eq!(
+            status,
+            vec![
+                RmStatus::Deleted,
+                RmStatus::Deleted,
+                RmStatus::Gone,
+                RmStatus::Deleted
+            ]
+        );
+        assert!(ls_snapshot(&dir).unwrap().is_empty());
+
+        // 符号链接指向别的目录: 拒绝删除
+        let other = tmp.join("other");
+        fs::create_dir_all(&other).unwrap();
+        let s = dir.join("2025/1756392926");
+        symlink(&other, &s).unwrap();
+        let r = safe_rm_subvol_list(&c, &config, vec![s.to_string_lossy().to_string()], &opt);
+        assert_eq!(r.unwrap()[0].1.status, RmStatus::Failed);
+        assert!(other.is_dir());
+        let meta_dir = dir.join(DIR_META);
+        assert_eq!(read_dir(meta_dir).unwrap().count(), 0);
+    }
+}
@@ -0,0 +1,75 @@
+//! 创建/删除快照的具体实现 (后端)
+//!
+//! 默认使用 btrfs 命令行 ([`BtrfsBackend`]).
+//! 普通目录实现 ([`DirBackend`]) 不需要 btrfs 文件系统, 用于测试和试运行.
+use std::path::{Path, PathBuf};
+
+use log::{debug, warn};
+
+use crate::{config::ConfigEnv, error::PmbsResult};
+
+mod btrfs;
+mod dir;
+
+pub use btrfs::{BtrfsBackend, parse_subvol_show};
+pub use dir::DirBackend;
+
+/// PMBS_BACKEND=btrfs
+pub const BACKEND_BTRFS: &str = "btrfs";
+/// PMBS_BACKEND=dir
+pub const BACKEND_DIR: &str = "dir";
+
+/// subvol (快照) 信息
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SubvolInfo {
+    /// UUID
+    pub uuid: String,
+    /// 快照的来源 subvol 的 UUID
+    pub parent_uuid: Option<String>,
+    /// 当前 generation (subvol 每次修改都会增加)
+    pub generation: u64,
+    /// 创建 subvol (快照) 时的 generation
+    pub gen_at_creation: u64,
+    /// 是否只读
+    pub readonly: bool,
+}
+
+/// 快照后端
+pub trait SnapshotBackend {
+    /// 创建只读快照, 比如 `btrfs subvol snapshot -r SUBVOL TO`
+    fn snapshot(&self, subvol: &Path, to: &Path) -> PmbsResult<()>;
+
+    /// 删除快照, 比如 `btrfs subvol delete PATH`
+    fn delete(&self, path: &Path) -> PmbsResult<()>;
+
+    /// 删除一批快照 (一个命令), 比如 `btrfs subvol delete PATH1 PATH2 ..`
+    fn delete_batch(&self, list: &[PathBuf]) -> PmbsResult<()> {
+        for p in list {
+            self.delete(p)?;
+        }
+        Ok(())
+    }
+
+    /// 获取 subvol (快照) 信息, 比如 `btrfs subvol show PATH`
+    fn show(&self, path: &Path) -> PmbsResult<SubvolInfo>;
+
+    /// 列出目录之下 (一层) 的所有 subvol (快照)
+    fn list(&self, dir: &Path) -> PmbsResult<Vec<PathBuf>>;
+
+    /// 等待删除的快照释放存储空间, 比如 `btrfs subvol sync PATH`
+    fn sync(&self, path: &Path) -> PmbsResult<()>;
+}
+
+/// 根据环境变量配置, 获取使用的后端
+pub fn get_backend(c: &ConfigEnv) -> Box<dyn SnapshotBackend> {
+    debug!("backend {}", c.backend);
+
+    match c.backend.as_str() {
+        BACKEND_BTRFS => Box::new(BtrfsBackend::new(&c.bin_btrfs, &c.bin_ionice)),
+        BACKEND_DIR => Box::new(DirBackend::new()),
+        _ => {
+            warn!("unknown backend {}, use {}", c.backend, BACKEND_BTRFS);
+            Box::new(BtrfsBackend::new(&c.bin_btrfs, &c.bin_ionice))
+        }
+    }
+}
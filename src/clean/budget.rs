@@ -0,0 +1,104 @@
+//! 剩余存储空间预算: 剩余空间不足时, 选择价值最低的快照
+//!
+//! 快照按照年龄 (距离最新快照的时间) 分段, 快照最密集 (单位时间内快照个数最多)
+//! 的分段中, 最旧的快照价值最低.
+use std::cmp::Reverse;
+
+use super::Snapshot;
+
+/// 快照年龄分段 (秒): 1 小时, 1 天, 7 天, 30 天, 365 天
+pub const AGE_BAND: [(u64, &str); 5] = [
+    (3600, "1h"),
+    (86400, "1d"),
+    (86400 * 7, "7d"),
+    (86400 * 30, "30d"),
+    (86400 * 365, "365d"),
+];
+
+/// 年龄分段: (开始, 结束, 名称), 比如 `(3600, 86400, "1h ~ 1d")`
+pub fn age_tier() -> Vec<(u64, u64, String)> {
+    let mut o = Vec::new();
+    let mut lo: Option<(u64, &str)> = None;
+    for (s, name) in AGE_BAND {
+        match lo {
+            Some((l, l_name)) => o.push((l, s, format!("{} ~ {}", l_name, name))),
+            None => o.push((0, s, format!("< {}", name))),
+        }
+        lo = Some((s, name));
+    }
+    if let Some((l, l_name)) = lo {
+        o.push((l, u64::MAX, format!(">= {}", l_name)));
+    }
+    o
+}
+
+/// 选择下一个要删除的快照 (价值最低)
+///
+/// 固定的快照, 以及最新的 `floor` 个快照, 永远不会被选择.
+///
+/// 返回: (快照, 年龄分段名称), 没有可以删除的快照时返回 None
+pub fn budget_pick(keep: &[Snapshot], floor: u32) -> Option<(Snapshot, String)> {
+    let mut list: Vec<&Snapshot> = keep.iter().filter(|x| !x.pinned).collect();
+    // 排序 (按时间降序, 最新的在最前面)
+    list.sort_by_key(|x| Reverse(x.t));
+    let newest = list.first()?.t;
+    let age = |x: &Snapshot| newest - x.t;
+
+    // 最密集的分段: (快照个数, 分段长度, 最旧的候选快照, 分段名称)
+    let mut best: Option<(u64, u64, &Snapshot, String)> = None;
+    for (lo, hi, name) in age_tier() {
+        let n = list.iter().filter(|x| (lo..hi).contains(&age(x))).count() as u64;
+        // 最后一个分段没有上限, 按照上一个分段的长度计算
+        let width = if hi == u64::MAX { lo } else { hi - lo };
+        let old = list
+            .iter()
+            .skip(floor as usize)
+            .rfind(|x| (lo..hi).contains(&age(x)));
+        if let Some(old) = old {
+            // 比较密集程度: n / width
+            let denser = match &best {
+                Some((bn, bw, _, _)) => {
+                    (n as u128) * (*bw as u128) > (*bn as u128) * (width as u128)
+                }
+                None => true,
+            };
+            if denser {
+                best = Some((n, width, old, name));
+            }
+        }
+    }
+    best.map(|(_, _, s, name)| (s.clone(), name))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn pick() {
+        let t = 1756392923;
+        // 最近 1 小时: 每 10 分钟 1 个; 1 天之内: 每小时 1 个; 以及 3 个更旧的
+        let mut keep: Vec<Snapshot> = (0..6).map(|i| Snapshot::new_t(t - i * 600)).collect();
+        keep.extend((1..24).map(|i| Snapshot::new_t(t - i * 3600)));
+        keep.extend((1..4).map(|i| Snapshot::new_t(t - i * 86400 * 2)));
+
+        // 最密集的分段: 最近 1 小时
+        let (s, tier) = budget_pick(&keep, 1).unwrap();
+        assert_eq!((s.t, tier.as_str()), (t - 3000, "< 1h"));
+
+        // 最近 1 小时的快照都在 floor 之内: 下一个分段
+        let (s, tier) = budget_pick(&keep, 6).unwrap();
+        assert_eq!((s.t, tier.as_str()), (t - 23 * 3600, "1h ~ 1d"));
+
+        // 固定的快照不会被选择
+        keep.retain(|x| x.t >= t - 3600 * 2 || x.t < t - 86400);
+        keep.iter_mut()
+            .filter(|x| x.t < t && x.t > t - 3600)
+            .for_each(|x| x.pinned = true);
+        let (s, tier) = budget_pick(&keep, 1).unwrap();
+        assert_eq!((s.t, tier.as_str()), (t - 7200, "1h ~ 1d"));
+
+        // 只剩下 floor
+        assert!(budget_pick(&keep, 100).is_none());
+    }
+}
@@ -0,0 +1,144 @@
+//! 日历时间段保留规则 (hourly/daily/weekly/monthly/yearly)
+//!
+//! 按照本地时间, 把快照分到日历时间段 (比如每天) 中,
+//! 保留最近 n 个 (有快照的) 时间段, 每个时间段保留第一个或最后一个快照.
+use std::{cmp::Reverse, fmt::Display};
+
+use chrono::{DateTime, Local, TimeZone};
+
+use crate::config::{Calendar, Pick, PmbsConfigKeep};
+
+use super::Snapshot;
+
+/// 快照所在的日历时间段, 比如 `2025-08-28`
+fn calendar_bucket_tz<Tz: TimeZone>(c: Calendar, t: u64, tz: &Tz) -> String
+where
+    Tz::Offset: Display,
+{
+    let f = match c {
+        Calendar::Hourly => "%Y-%m-%dT%H",
+        Calendar::Daily => "%Y-%m-%d",
+        // ISO 8601 周 (年份也按照 ISO 周计算)
+        Calendar::Weekly => "%G-W%V",
+        Calendar::Monthly => "%Y-%m",
+        Calendar::Yearly => "%Y",
+    };
+    DateTime::from_timestamp(t as i64, 0)
+        .unwrap_or_default()
+        .with_timezone(tz)
+        .format(f)
+        .to_string()
+}
+
+/// 执行日历规则 (同一类别的快照, 不含固定的快照)
+///
+/// 返回: 被保留的快照 (时间戳, 时间段), 比如 `(1756392923, "daily 2025-08-28")`
+pub(super) fn keep_calendar(rule: &[PmbsConfigKeep], snapshot: &[Snapshot]) -> Vec<(u64, String)> {
+    keep_calendar_tz(rule, snapshot, &Local)
+}
+
+fn keep_calendar_tz<Tz: TimeZone>(
+    rule: &[PmbsConfigKeep],
+    snapshot: &[Snapshot],
+    tz: &Tz,
+) -> Vec<(u64, String)>
+where
+    Tz::Offset: Display,
+{
+    let mut t: Vec<u64> = snapshot.iter().map(|x| x.t).collect();
+    // 排序 (按时间降序, 最新的在最前面)
+    t.sort_by_key(|x| Reverse(*x));
+
+    let mut keep: Vec<(u64, String)> = Vec::new();
+    for r in rule {
+        let c = match r.calendar {
+            Some(c) => c,
+            None => continue,
+        };
+        // 时间段: (名称, 第一个快照, 最后一个快照)
+        let mut bucket: Vec<(String, u64, u64)> = Vec::new();
+        for i in &t {
+            let b = calendar_bucket_tz(c, *i, tz);
+            match bucket.last_mut() {
+                // 时间降序, 所以后面的快照更旧
+                Some(last) if last.0 == b => last.1 = *i,
+                _ => {
+                    // 只保留最近 n 个时间段
+                    if bucket.len() >= r.n as usize {
+                        break;
+                    }
+                    bucket.push((b, *i, *i));
+                }
+            }
+        }
+        for (b, first, last) in bucket {
+            let i = match r.pick {
+                Pick::First => first,
+                Pick::Last => last,
+            };
+            keep.push((i, format!("{} {}", c.as_str(), b)));
+        }
+    }
+    keep
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use chrono::FixedOffset;
+
+    #[test]
+    fn bucket() {
+        let utc = FixedOffset::east_opt(0).unwrap();
+        // 2025-08-28T14:55:23Z (周四)
+        let t = 1756392923;
+        assert_eq!(
+            calendar_bucket_tz(Calendar::Hourly, t, &utc),
+            "2025-08-28T14"
+        );
+        assert_eq!(calendar_bucket_tz(Calendar::Daily, t, &utc), "2025-08-28");
+        assert_eq!(calendar_bucket_tz(Calendar::Weekly, t, &utc), "2025-W35");
+        assert_eq!(calendar_bucket_tz(Calendar::Monthly, t, &utc), "2025-08");
+        assert_eq!(calendar_bucket_tz(Calendar::Yearly, t, &utc), "2025");
+        // 本地时间
+        let cst = FixedOffset::east_opt(8 * 3600).unwrap();
+        assert_eq!(
+            calendar_bucket_tz(Calendar::Daily, t + 3 * 3600, &cst),
+            "2025-08-29"
+        );
+    }
+
+    #[test]
+    fn keep_daily() {
+        let utc = FixedOffset::east_opt(0).unwrap();
+        // 2025-08-28T00:00:00Z
+        let d = 1756339200;
+        // 每 6 小时一个快照, 共 4 天 (2025-08-25 ~ 2025-08-28)
+        let snapshot: Vec<Snapshot> = (0..16)
+            .map(|i| Snapshot::new_t(d + 18 * 3600 - i * 6 * 3600))
+            .collect();
+
+        let last = vec![PmbsConfigKeep::new_calendar(Calendar::Daily, 3, Pick::Last)];
+        assert_eq!(
+            keep_calendar_tz(&last, &snapshot, &utc),
+            vec![
+                (d + 18 * 3600, "daily 2025-08-28".to_string()),
+                (d - 86400 + 18 * 3600, "daily 2025-08-27".to_string()),
+                (d - 86400 * 2 + 18 * 3600, "daily 2025-08-26".to_string()),
+            ]
+        );
+
+        let first = vec![PmbsConfigKeep::new_calendar(
+            Calendar::Daily,
+            2,
+            Pick::First,
+        )];
+        assert_eq!(
+            keep_calendar_tz(&first, &snapshot, &utc),
+            vec![
+                (d, "daily 2025-08-28".to_string()),
+                (d - 86400, "daily 2025-08-27".to_string()),
+            ]
+        );
+    }
+}
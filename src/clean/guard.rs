@@ -0,0 +1,229 @@
+//! 全局限制: max_age, max_total, min_keep
+//!
+//! 在保留规则 (所有类别) 之后执行, 强制 保留/清理 快照.
+use std::cmp::Reverse;
+
+use log::{debug, warn};
+
+use crate::config::PmbsConfig;
+
+use super::{Reason, Snapshot};
+
+/// 强制 保留/清理 的原因
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Force {
+    /// 快照太旧, 强制清理
+    MaxAge,
+    /// 快照总数太多, 强制清理 (最旧的)
+    MaxTotal,
+    /// 快照总数太少, 强制保留 (最新的)
+    MinKeep,
+}
+
+impl Force {
+    /// 配置文件中的名称
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::MaxAge => "max_age",
+            Self::MaxTotal => "max_total",
+            Self::MinKeep => "min_keep",
+        }
+    }
+}
+
+/// 清理决定
+#[derive(Debug, Clone, Default)]
+pub struct Decision {
+    /// 保留的快照 (按时间降序)
+    pub keep: Vec<Snapshot>,
+    /// 清理的快照 (按时间升序, 清理应该从最旧的开始)
+    pub clean: Vec<Snapshot>,
+    /// 全局限制强制 保留/清理 的快照: (时间戳, 原因)
+    pub force: Vec<(u64, Force)>,
+    /// 保留规则 保留/清理 快照的原因: (时间戳, 原因)
+    pub reason: Vec<(u64, Reason)>,
+}
+
+impl Decision {
+    /// 快照被强制 保留/清理 的原因
+    pub fn get_force(&self, t: u64) -> Option<Force> {
+        self.force.iter().find(|x| x.0 == t).map(|x| x.1)
+    }
+
+    /// 保留规则 保留/清理 快照的原因
+    pub fn get_reason(&self, t: u64) -> Option<&Reason> {
+        self.reason.iter().find(|x| x.0 == t).map(|x| &x.1)
+    }
+}
+
+/// 执行全局限制 (固定的快照总是保留)
+///
+/// - `max_age`: 比最新的快照早这么久的快照, 全部清理
+/// - `max_total`: 快照总数 (含固定的快照) 超过时, 清理最旧的.
+///   不清理最新的 (不是固定的) 快照, 以及 `keep_latest` 之内的快照 (`reason`)
+/// - `min_keep`: 快照总数少于时, 保留最新的 (优先于 max_age)
+pub(super) fn guard(
+    config: &PmbsConfig,
+    mut keep: Vec<Snapshot>,
+    mut clean: Vec<Snapshot>,
+    reason: &[(u64, Reason)],
+) -> Decision {
+    let mut force: Vec<(u64, Force)> = Vec::new();
+    // 基准时间戳: 最新的快照
+    let newest = keep.iter().chain(clean.iter()).map(|x| x.t).max();
+
+    if config.max_age_s > 0
+        && let Some(newest) = newest
+    {
+        let (old, k): (Vec<Snapshot>, Vec<Snapshot>) = keep
+            .into_iter()
+            .partition(|x| !x.pinned && (newest - x.t) > config.max_age_s);
+        keep = k;
+        for i in old {
+            force.push((i.t, Force::MaxAge));
+            clean.push(i);
+        }
+    }
+
+    // 排序 (按时间降序, 最新的在最前面)
+    keep.sort_by_key(|x| Reverse(x.t));
+    if let Some(max_total) = config.max_total {
+        // 最新的 (不是固定的) 快照, 也就是 latest
+        let newest_free = keep.iter().filter(|x| !x.pinned).map(|x| x.t).max();
+        // 不能强制清理: 固定的, 最新的, keep_latest 之内的
+        let protect = |x: &Snapshot| {
+            x.pinned
+                || Some(x.t) == newest_free
+                || reason.iter().any(|r| r.0 == x.t && r.1 == Reason::Latest)
+        };
+        while keep.len() > (max_total as usize) {
+            // 最旧的 (可以清理的) 快照
+            match keep.iter().rposition(|x| !protect(x)) {
+                Some(i) => {
+                    let s = keep.remove(i);
+                    force.push((s.t, Force::MaxTotal));
+                    clean.push(s);
+                }
+                None => {
+                    warn!(
+                        "too many pinned or latest snapshots !  {} > max_total = {}",
+                        keep.len(),
+                        max_total
+                    );
+                    break;
+                }
+            }
+        }
+    }
+
+    if let Some(min_keep) = config.min_keep {
+        clean.sort_by_key(|x| Reverse(x.t));
+        while keep.len() < (min_keep as usize) && !clean.is_empty() {
+            let s = clean.remove(0);
+            force.retain(|x| x.0 != s.t);
+            force.push((s.t, Force::MinKeep));
+            keep.push(s);
+        }
+    }
+
+    // 排序 (清理应该从最旧的开始)
+    clean.sort_by_key(|a| a.t);
+    keep.sort_by_key(|x| Reverse(x.t));
+    debug!("force  {:?}", force);
+    Decision {
+        keep,
+        clean,
+        force,
+        reason: Vec::new(),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn t_list(list: &[Snapshot]) -> Vec<u64> {
+        list.iter().map(|x| x.t).collect()
+    }
+
+    #[test]
+    fn max_age_total_min_keep() {
+        let t = 1756392923;
+        let s = |i: u64| Snapshot::new_t(t - i * 86400);
+        let keep: Vec<Snapshot> = (0..5).map(s).collect();
+        let clean: Vec<Snapshot> = (5..8).map(s).collect();
+
+        let mut c = PmbsConfig::new_subvol("/");
+        // 没有全局限制
+        let d = guard(&c, keep.clone(), clean.clone(), &[]);
+        assert_eq!(t_list(&d.keep), t_list(&keep));
+        assert!(d.force.is_empty());
+
+        // 超过 2 天的快照, 全部清理 (固定的除外)
+        c.max_age_s = 86400 * 2;
+        let mut k = keep.clone();
+        k[4].pinned = true;
+        let d = guard(&c, k.clone(), clean.clone(), &[]);
+        assert_eq!(
+            t_list(&d.keep),
+            vec![t, t - 86400, t - 86400 * 2, t - 86400 * 4]
+        );
+        assert_eq!(d.get_force(t - 86400 * 3), Some(Force::MaxAge));
+        assert_eq!(d.clean.len(), 4);
+
+        // 最多 2 个
+        c.max_age_s = 0;
+        c.max_total = Some(2);
+        let d = guard(&c, k.clone(), clean.clone(), &[]);
+        assert_eq!(t_list(&d.keep), vec![t, t - 86400 * 4]);
+        assert_eq!(d.get_force(t - 86400), Some(Force::MaxTotal));
+
+        // 至少 7 个: 从清理列表中取回最新的
+        c.max_total = None;
+        c.min_keep = Some(7);
+        let d = guard(&c, keep.clone(), clean.clone(), &[]);
+        assert_eq!(d.keep.len(), 7);
+        assert_eq!(t_list(&d.clean), vec![t - 86400 * 7]);
+        assert_eq!(d.get_force(t - 86400 * 5), Some(Force::MinKeep));
+        assert_eq!(d.get_force(t - 86400 * 7), None);
+
+        // min_keep 优先于 max_age
+        c.max_age_s = 86400;
+        c.min_keep = Some(3);
+        let d = guard(&c, keep, clean, &[]);
+        assert_eq!(t_list(&d.keep), vec![t, t - 86400, t - 86400 * 2]);
+        assert_eq!(d.get_force(t - 86400 * 2), Some(Force::MinKeep));
+        assert_eq!(d.get_force(t - 86400 * 3), Some(Force::MaxAge));
+    }
+
+    /// 固定的快照占满 max_total: 不清理最新的快照, 以及 keep_latest 之内的
+    #[test]
+    fn max_total_pinned() {
+        let t = 1756392923;
+        let mut c = PmbsConfig::new_subvol("/");
+        c.max_total = Some(2);
+        let s = |i: u64, pinned: bool| Snapshot {
+            pinned,
+            ..Snapshot::new_t(t - i * 86400)
+        };
+        // 2 个固定的快照, 加上 1 个新的快照
+        let keep = vec![s(0, false), s(1, true), s(2, true)];
+        let d = guard(&c, keep.clone(), Vec::new(), &[]);
+        assert_eq!(t_list(&d.keep), t_list(&keep));
+        assert!(d.clean.is_empty());
+
+        // 最新的快照是固定的: 之后最新的 (latest) 仍然保留
+        let keep = vec![s(0, true), s(1, true), s(2, false), s(3, false)];
+        let d = guard(&c, keep, Vec::new(), &[]);
+        assert_eq!(t_list(&d.keep), vec![t, t - 86400, t - 86400 * 2]);
+        assert_eq!(t_list(&d.clean), vec![t - 86400 * 3]);
+
+        // keep_latest 之内的快照也不清理
+        let keep = vec![s(0, false), s(1, false), s(2, false), s(3, false)];
+        let reason = vec![(t, Reason::Latest), (t - 86400, Reason::Latest)];
+        c.max_total = Some(1);
+        let d = guard(&c, keep, Vec::new(), &reason);
+        assert_eq!(t_list(&d.keep), vec![t, t - 86400]);
+        assert_eq!(d.get_force(t - 86400 * 2), Some(Force::MaxTotal));
+    }
+}
@@ -1,14 +1,20 @@
 //! 列出现有的所有快照
 use std::{
     fs::{read_dir, read_link},
-    path::PathBuf,
+    path::{Path, PathBuf},
     str::FromStr,
 };
 
 use log::{debug, warn};
 use regex::Regex;
+use serde::Serialize;
 
-use crate::snapshot::{DIR_PMBS, SYMLINK_LATEST};
+use crate::{
+    error::{PmbsError, PmbsResult},
+    snapshot::{SYMLINK_LATEST, SnapshotMeta, Trigger, get_t, get_year, read_meta},
+};
+
+use super::pin::read_pin;
 
 /// 检查年, 比如 `/.pmbs/2025/1756392923` 中的 `2025`
 pub fn get_re_year() -> Regex {
@@ -23,7 +29,10 @@ pub fn get_re_t() -> Regex {
 }
 
 /// 一个快照
-#[derive(Debug, Clone)]
+///
+/// 也用于 `--json` 输出, 字段见 `doc/pmbs.md` 的 "3.1 JSON 输出".
+/// 增加字段不改变格式版本 `version`; 删除字段, 或者修改字段的含义时, 增加版本.
+#[derive(Debug, Clone, Serialize)]
 pub struct Snapshot {
     /// 路径 (字符串 2025/1756392923 格式)
     pub path: String,
@@ -34,88 +43,131 @@ pub struct Snapshot {
     /// 是否被 latest 符号链接指向
     pub latest: bool,
     /// 路径 (PathBuf)
+    #[serde(rename = "full_path")]
     pub p: PathBuf,
+    /// 元数据 (/.pmbs/meta/T.json)
+    pub meta: Option<SnapshotMeta>,
+    /// 是否被固定 (/.pmbs/pin.json)
+    pub pinned: bool,
 }
 
-/// 列出指定目录下的所有快照
-pub fn ls_snapshot(path: &str) -> Vec<Snapshot> {
+impl Snapshot {
+    /// 用于调试: 只含有时间戳的快照
+    pub fn new_t(t: u64) -> Self {
+        let year = get_year(t);
+        let path = format!("{}/{}", year, t);
+        Self {
+            p: PathBuf::from(&path),
+            path,
+            year,
+            t,
+            latest: false,
+            meta: None,
+            pinned: false,
+        }
+    }
+
+    /// 快照类别 (触发方式), 没有元数据的快照属于 timer
+    pub fn get_class(&self) -> Trigger {
+        self.meta.as_ref().map(|x| x.trigger).unwrap_or_default()
+    }
+}
+
+/// 是目录 (不跟随符号链接)
+fn is_dir_nofollow(p: &Path) -> bool {
+    p.symlink_metadata().is_ok_and(|m| m.is_dir())
+}
+
+/// 列出快照保存目录 (比如 /home/.pmbs) 下的所有快照
+///
+/// 不跟随符号链接: 符号链接的年目录和快照会被忽略 (输出警告).
+pub fn ls_snapshot(dir: &Path) -> PmbsResult<Vec<Snapshot>> {
     let mut o: Vec<Snapshot> = Vec::new();
-    // 检查 path/.pmbs 是否存在
-    let mut p = PathBuf::from(path);
-    p.push(DIR_PMBS);
-    if p.is_dir() {
-        // 读取 latest
-        let mut p_latest = p.clone();
-        p_latest.push(SYMLINK_LATEST);
-        let latest_symlink: Option<String> = match read_link(&p_latest) {
-            Ok(p) => Some(p.to_string_lossy().to_string()),
+    // 检查 .pmbs 是否存在
+    let p = dir.to_path_buf();
+    if !is_dir_nofollow(&p) {
+        debug!("dir not exist  {}", p.to_string_lossy());
+        return Ok(o);
+    }
+    // 读取 latest
+    let mut p_latest = p.clone();
+    p_latest.push(SYMLINK_LATEST);
+    let latest_symlink: Option<String> = match read_link(&p_latest) {
+        Ok(p) => Some(p.to_string_lossy().to_string()),
+        _ => {
+            debug!("symlink not exist  {}", p_latest.to_string_lossy());
+            None
+        }
+    };
+
+    // 读取固定快照列表
+    let pin = read_pin(&p);
+    let now = get_t();
+
+    // 初始化正则表达式 (避免在循环内)
+    let re_year = get_re_year();
+    let re_t = get_re_t();
+
+    // 列出年
+    for i in read_dir(&p).map_err(PmbsError::io(&p))? {
+        let d = i.map_err(PmbsError::io(&p))?;
+        // 检查名称
+        let name = d.file_name().to_string_lossy().to_string();
+        if !re_year.is_match(&name) {
+            // 忽略
+            continue;
+        }
+        let p_year = d.path();
+        let year: i32 = match FromStr::from_str(&name) {
+            Ok(year) if is_dir_nofollow(&p_year) => year,
             _ => {
-                debug!("symlink not exist  {}", p_latest.to_string_lossy());
-                None
+                warn!("not dir  {}", p_year.to_string_lossy());
+                continue;
             }
         };
 
-        // 初始化正则表达式 (避免在循环内)
-        let re_year = get_re_year();
-        let re_t = get_re_t();
-
-        // 列出年
-        for i in read_dir(p).unwrap() {
-            let d = i.unwrap();
+        // 列出时间戳
+        for i in read_dir(&p_year).map_err(PmbsError::io(&p_year))? {
+            let d = i.map_err(PmbsError::io(&p_year))?;
             // 检查名称
             let name = d.file_name().to_string_lossy().to_string();
-            if !re_year.is_match(&name) {
+            if !re_t.is_match(&name) {
                 // 忽略
                 continue;
             }
-
-            let p_year = d.path();
-            if p_year.is_dir() {
-                let year: i32 = FromStr::from_str(&name).unwrap();
-
-                // 列出时间戳
-                for i in read_dir(p_year).unwrap() {
-                    let d = i.unwrap();
-                    // 检查名称
-                    let name = d.file_name().to_string_lossy().to_string();
-                    if !re_t.is_match(&name) {
-                        // 忽略
-                        continue;
-                    }
-
-                    let p_t = d.path();
-                    if p_t.is_dir() {
-                        let t: u64 = FromStr::from_str(&name).unwrap();
-
-                        let path = format!("{}/{}", year, t);
-                        let latest = match &latest_symlink {
-                            Some(p) => &path == p,
-                            None => false,
-                        };
-                        // 发现一个快照
-                        o.push(Snapshot {
-                            path,
-                            year,
-                            t,
-                            latest,
-                            p: p_t,
-                        });
-                    } else {
-                        warn!("not dir  {}", p_t.to_string_lossy());
-                    }
+            let p_t = d.path();
+            let t: u64 = match FromStr::from_str(&name) {
+                Ok(t) if is_dir_nofollow(&p_t) => t,
+                _ => {
+                    warn!("not dir  {}", p_t.to_string_lossy());
+                    continue;
                 }
-            } else {
-                warn!("not dir  {}", p_year.to_string_lossy());
-            }
+            };
+
+            let path = format!("{}/{}", year, t);
+            let latest = match &latest_symlink {
+                Some(p) => &path == p,
+                None => false,
+            };
+            let pinned = pin.is_pinned(&path, now);
+            // 发现一个快照
+            o.push(Snapshot {
+                pinned,
+                path,
+                year,
+                t,
+                latest,
+                p: p_t,
+                meta: read_meta(&p, t),
+            });
         }
-    } else {
-        debug!("dir not exist  {}", p.to_string_lossy());
     }
-    o
+    Ok(o)
 }
 
 /// 对正则表达式匹配进行测试
 #[cfg(test)]
+#[allow(clippy::bool_assert_comparison)]
 mod test_re {
     use super::*;
 
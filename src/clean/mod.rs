@@ -1,13 +1,32 @@
 //! 自动清理
+use std::cmp::Reverse;
+
 use log::debug;
 
-use crate::config::PmbsConfigKeep;
+use crate::{
+    config::{PmbsConfig, PmbsConfigKeep},
+    snapshot::Trigger,
+};
 
+mod budget;
+mod calendar;
+mod guard;
 mod ls;
+mod pin;
+mod plan;
 mod safe_rm_subvol;
+mod simulate;
 
+pub use budget::{AGE_BAND, age_tier, budget_pick};
+pub use guard::{Decision, Force};
 pub use ls::{Snapshot, get_re_t, get_re_year, ls_snapshot};
-pub use safe_rm_subvol::{get_re_safe_check_path, safe_rm_subvol_list};
+pub use pin::{FILE_PIN, Pin, PinList, read_pin, write_pin};
+pub use plan::Reason;
+pub use safe_rm_subvol::{
+    RmOpt, RmResult, RmStatus, get_re_safe_check_path, get_re_safe_check_path_dir,
+    safe_rm_subvol_list,
+};
+pub use simulate::{SimulateOpt, SimulateReport, simulate};
 
 /// 保留规则生成器, 一次输出一条待使用的规则 (间隔时间/秒)
 #[derive(Debug, Clone)]
@@ -18,18 +37,30 @@ struct KeepIter {
     i: usize,
     // 当前规则
     r: Option<PmbsConfigKeep>,
+    // 最后输出的规则: (规则序号, 第几个)
+    last: Option<(usize, u32)>,
 }
 
 impl KeepIter {
     /// 创建
     pub fn new(rule: Vec<PmbsConfigKeep>) -> Self {
-        let r = if rule.len() > 0 {
+        let r = if !rule.is_empty() {
             Some(rule[0].clone())
         } else {
             None
         };
 
-        Self { rule, i: 0, r }
+        Self {
+            rule,
+            i: 0,
+            r,
+            last: None,
+        }
+    }
+
+    /// 最后输出的规则: (规则序号, 第几个, 从 1 开始)
+    pub fn slot(&self) -> Option<(usize, u32)> {
+        self.last
     }
 
     /// 检查加载下一条规则
@@ -63,6 +94,7 @@ impl Iterator for KeepIter {
             Some(r) => {
                 // 消耗一条规则
                 r.n -= 1;
+                self.last = Some((self.i, self.rule[self.i].n - r.n));
 
                 Some(r.s)
             }
@@ -72,62 +104,173 @@ impl Iterator for KeepIter {
     }
 }
 
+fn debug_snapshot_list(list: &[Snapshot]) -> String {
+    format!("{:?}", list.iter().map(|x| x.t).collect::<Vec<_>>())
+}
+
+/// 保留规则, 以及在配置中的序号
+type IndexRule = Vec<(usize, PmbsConfigKeep)>;
+
+/// 快照按照类别 (触发方式) 分组: (类别, 保留规则, 快照)
+///
+/// 没有单独保留规则的类别, 和 timer 类别的快照一起, 使用默认的保留规则.
+fn group_class(
+    rule: &[PmbsConfigKeep],
+    snapshot: Vec<Snapshot>,
+) -> Vec<(Trigger, IndexRule, Vec<Snapshot>)> {
+    let mut group = Vec::new();
+
+    // 有单独保留规则的类别
+    let class: Vec<Trigger> = [Trigger::Manual, Trigger::Hook]
+        .into_iter()
+        .filter(|c| rule.iter().any(|r| r.get_class() == *c))
+        .collect();
+    let get_class = |x: &Snapshot| {
+        let c = x.get_class();
+        if class.contains(&c) {
+            c
+        } else {
+            Trigger::Timer
+        }
+    };
+
+    let mut snapshot = snapshot;
+    for c in [Trigger::Timer, Trigger::Manual, Trigger::Hook] {
+        let (s, rest): (Vec<Snapshot>, Vec<Snapshot>) =
+            snapshot.into_iter().partition(|x| get_class(x) == c);
+        snapshot = rest;
+        if s.is_empty() {
+            continue;
+        }
+        let r = rule
+            .iter()
+            .enumerate()
+            .filter(|(_, r)| r.get_class() == c)
+            .map(|(i, r)| (i, r.clone()))
+            .collect();
+        group.push((c, r, s));
+    }
+    group
+}
+
 /// 决定 保留/清理 快照 (自动清理核心算法)
 ///
-/// 返回: (保留列表, 清理列表)
-pub fn decide(
-    rule: Vec<PmbsConfigKeep>,
-    mut snapshot: Vec<Snapshot>,
-) -> (Vec<Snapshot>, Vec<Snapshot>) {
-    fn debug_snapshot_list(list: &Vec<Snapshot>) -> String {
-        format!("{:?}", list.iter().map(|x| x.t).collect::<Vec<_>>())
+/// 快照按照类别 (触发方式) 分组, 每组使用各自的保留规则.
+/// 间隔时间规则和日历规则同时使用, 任意一种规则保留的快照都会保留.
+///
+/// 使用配置中的 `keep`, `keep_latest_s`, `keep_tolerance_s`.
+/// 最后执行全局限制 (`max_age`, `max_total`, `min_keep`).
+///
+/// 结果中含有每个快照 保留/清理 的原因 ([`Reason`]).
+pub fn decide(config: &PmbsConfig, snapshot: Vec<Snapshot>) -> Decision {
+    let mut keep: Vec<Snapshot> = Vec::new();
+    let mut clean: Vec<Snapshot> = Vec::new();
+    let mut reason: Vec<(u64, Reason)> = Vec::new();
+
+    for (c, r, s) in group_class(&config.keep, snapshot) {
+        debug!("class {}", c.as_str());
+
+        let d = decide_class(r, s, config.keep_latest_s, config.keep_tolerance_s);
+        keep.extend(d.keep);
+        clean.extend(d.clean);
+        reason.extend(d.reason);
     }
+    let d = guard::guard(config, keep, clean, &reason);
+    Decision { reason, ..d }
+}
+
+/// 日历规则保留的快照 (用于显示): (时间戳, 时间段)
+///
+/// 一个快照可能被多条日历规则保留. 固定的快照不占用日历规则.
+pub fn decide_calendar(rule: &[PmbsConfigKeep], snapshot: Vec<Snapshot>) -> Vec<(u64, String)> {
+    group_class(rule, snapshot)
+        .into_iter()
+        .flat_map(|(_, r, s)| {
+            let r: Vec<PmbsConfigKeep> = r.into_iter().map(|x| x.1).collect();
+            let s: Vec<Snapshot> = s.into_iter().filter(|x| !x.pinned).collect();
+            calendar::keep_calendar(&r, &s)
+        })
+        .collect()
+}
 
+/// 对同一类别的快照, 执行保留规则 (不含全局限制)
+///
+/// - `keep_latest`: 最新快照之前这段时间 (秒) 内的快照, 全部保留
+/// - `keep_s`: 容忍系统时间误差 (秒), 时间检查减少这个值
+fn decide_class(
+    rule: IndexRule,
+    mut snapshot: Vec<Snapshot>,
+    keep_latest: u64,
+    keep_s: u64,
+) -> Decision {
     debug!("rule  {:?}", rule);
+    // 日历规则, 单独处理
+    let (rule_calendar, rule): (IndexRule, IndexRule) =
+        rule.into_iter().partition(|x| x.1.calendar.is_some());
+    // 规则在配置中的序号
+    let (index, rule): (Vec<usize>, Vec<PmbsConfigKeep>) = rule.into_iter().unzip();
 
     // 排序 (按时间降序, 最新的在最前面)
-    snapshot.sort_by(|a, b| b.t.cmp(&a.t));
+    snapshot.sort_by_key(|x| Reverse(x.t));
 
     debug!("snapshot  {}", debug_snapshot_list(&snapshot));
+    // 基准时间戳: 当前的最新快照 (全部保留)
+    let t0 = snapshot.first().map(|x| x.t.saturating_sub(keep_latest));
+
+    // 固定的快照: 全部保留, 并且不占用保留规则
+    let (mut keep, snapshot): (Vec<Snapshot>, Vec<Snapshot>) =
+        snapshot.into_iter().partition(|x| x.pinned);
+    debug!("pinned  {}", debug_snapshot_list(&keep));
+    let mut reason: Vec<(u64, Reason)> = keep.iter().map(|x| (x.t, Reason::Pinned)).collect();
+    let not_pinned = snapshot.clone();
+
     // 保留规则生成器
     let mut ki = KeepIter::new(rule);
-    // 保留的快照列表
-    let mut keep: Vec<Snapshot> = Vec::new();
+    // 当前占用的规则
+    let slot = |ki: &KeepIter| {
+        ki.slot().map(|(i, n)| Reason::Rule {
+            rule: index[i],
+            slot: n,
+        })
+    };
     // 清理的快照列表
     let mut clean: Vec<Snapshot> = Vec::new();
 
-    // 硬编码: 最新快照之前 5 分钟的快照, 全部保留
-    const KEEP_LATEST: u64 = 300;
-    // 容忍系统时间误差: 时间检查减少 10 秒
-    const KEEP_S: u64 = 10;
-
     // 处理临时存储: 保留最后一条, 其余全部丢弃
-    fn clear_tmp(tmp: &mut Vec<Snapshot>, keep: &mut Vec<Snapshot>, clean: &mut Vec<Snapshot>) {
+    fn clear_tmp(
+        tmp: &mut Vec<Snapshot>,
+        keep: &mut Vec<Snapshot>,
+        clean: &mut Vec<Snapshot>,
+        reason: &mut Vec<(u64, Reason)>,
+        r: Option<Reason>,
+    ) {
         if let Some(i) = tmp.pop() {
+            // 其余的全部丢弃: 被这个快照遮盖
+            while let Some(x) = tmp.pop() {
+                reason.push((x.t, Reason::Masked { by: i.t }));
+                clean.push(x);
+            }
+            if let Some(r) = r {
+                reason.push((i.t, r));
+            }
             keep.push(i);
         }
-        // 其余的全部丢弃
-        clear_tmp_all(tmp, clean);
     }
 
-    fn clear_tmp_all(tmp: &mut Vec<Snapshot>, clean: &mut Vec<Snapshot>) {
-        loop {
-            match tmp.pop() {
-                Some(i) => {
-                    clean.push(i);
-                }
-                None => {
-                    break;
-                }
-            }
+    fn clear_tmp_all(
+        tmp: &mut Vec<Snapshot>,
+        clean: &mut Vec<Snapshot>,
+        reason: &mut Vec<(u64, Reason)>,
+    ) {
+        while let Some(i) = tmp.pop() {
+            reason.push((i.t, Reason::Exhausted));
+            clean.push(i);
         }
     }
 
-    if snapshot.len() > 0 {
+    if let Some(t0) = t0 {
         // 当前保留规则
         let mut rule = ki.next();
-        // 基准时间戳: 当前的最新快照 (硬编码保留)
-        let t0 = snapshot[0].t - KEEP_LATEST;
         // 当前保留时间戳
         let mut t = t0;
 
@@ -136,8 +279,9 @@ pub fn decide(
 
         // 决定每个快照的命运
         for i in snapshot {
-            // 硬编码: 最新快照之前 5 分钟的快照, 全部保留
-            if (i.t + KEEP_S) > t0 {
+            // 最新快照之前 keep_latest 内的快照, 全部保留
+            if (i.t + keep_s) > t0 {
+                reason.push((i.t, Reason::Latest));
                 keep.push(i.clone());
                 t = i.t;
                 continue;
@@ -145,13 +289,13 @@ pub fn decide(
             // 检查规则遮盖
             match rule {
                 Some(s) => {
-                    if i.t > (t - s + KEEP_S) {
+                    if i.t > (t.saturating_sub(s) + keep_s) {
                         // 被遮盖, 放入临时存储 (而不是直接丢弃)
                         tmp.push(i.clone());
                     } else {
                         // 未被遮盖
                         // 保留之前临时存储的最后一条 (一个被遮盖区间内, 保留最后一条)
-                        clear_tmp(&mut tmp, &mut keep, &mut clean);
+                        clear_tmp(&mut tmp, &mut keep, &mut clean, &mut reason, slot(&ki));
 
                         // 更新保留时间基准
                         t = i.t;
@@ -163,20 +307,44 @@ pub fn decide(
                 }
                 None => {
                     // 规则用尽, 全部丢弃
-                    clear_tmp_all(&mut tmp, &mut clean);
+                    clear_tmp_all(&mut tmp, &mut clean, &mut reason);
+                    reason.push((i.t, Reason::Exhausted));
                     clean.push(i.clone());
                 }
             }
         }
         // 处理剩余的临时存储
-        clear_tmp(&mut tmp, &mut keep, &mut clean);
+        clear_tmp(&mut tmp, &mut keep, &mut clean, &mut reason, slot(&ki));
+    }
+    // 日历规则: 从清理列表中取回被保留的快照
+    for (i, r) in &rule_calendar {
+        for (t, b) in calendar::keep_calendar(std::slice::from_ref(r), &not_pinned) {
+            if let Some(p) = clean.iter().position(|x| x.t == t) {
+                debug!("calendar  {}  {}", t, b);
+                keep.push(clean.remove(p));
+                reason.retain(|x| x.0 != t);
+                reason.push((
+                    t,
+                    Reason::Calendar {
+                        rule: *i,
+                        bucket: b,
+                    },
+                ));
+            }
+        }
     }
     // 排序 (清理应该从最旧的开始)
-    clean.sort_by(|a, b| a.t.cmp(&b.t));
+    clean.sort_by_key(|a| a.t);
+    keep.sort_by_key(|x| Reverse(x.t));
 
     debug!("keep  {}", debug_snapshot_list(&keep));
     debug!("clean  {}", debug_snapshot_list(&clean));
-    (keep, clean)
+    Decision {
+        keep,
+        clean,
+        reason,
+        ..Default::default()
+    }
 }
 
 #[cfg(test)]
@@ -199,4 +367,194 @@ mod test {
         assert_eq!(ki.next(), None);
         assert_eq!(ki.next(), None);
     }
+
+    fn t_list(list: &[Snapshot]) -> Vec<u64> {
+        list.iter().map(|x| x.t).collect()
+    }
+
+    /// 使用默认配置和指定的保留规则
+    fn config(rule: Vec<PmbsConfigKeep>) -> PmbsConfig {
+        PmbsConfig {
+            keep: rule,
+            ..PmbsConfig::new_subvol("/")
+        }
+    }
+
+    /// 固定的快照总是保留, 并且不占用保留规则
+    #[test]
+    fn decide_pinned() {
+        let t = 1756392923;
+        let rule = vec![PmbsConfigKeep::new_sn(600, 3)];
+        let mut snapshot: Vec<Snapshot> = (0..10).map(|i| Snapshot::new_t(t - i * 600)).collect();
+        snapshot[1].pinned = true;
+        snapshot[9].pinned = true;
+
+        // 不含固定快照时的结果
+        let Decision { keep, clean, .. } = decide(
+            &config(rule.clone()),
+            snapshot.iter().filter(|x| !x.pinned).cloned().collect(),
+        );
+        assert_eq!(t_list(&keep), vec![t, t - 1200, t - 1800]);
+        assert_eq!(
+            t_list(&clean),
+            vec![t - 4800, t - 4200, t - 3600, t - 3000, t - 2400]
+        );
+
+        let Decision { keep, clean, .. } = decide(&config(rule), snapshot);
+        assert_eq!(
+            t_list(&keep),
+            vec![t, t - 600, t - 1200, t - 1800, t - 5400]
+        );
+        assert_eq!(
+            t_list(&clean),
+            vec![t - 4800, t - 4200, t - 3600, t - 3000, t - 2400]
+        );
+    }
+
+    /// 不同类别的快照, 使用各自的保留规则
+    #[test]
+    fn decide_class() {
+        use crate::snapshot::SnapshotMeta;
+
+        let t = 1756392923;
+        let manual = |t: u64| {
+            let mut s = Snapshot::new_t(t);
+            s.meta = Some(SnapshotMeta {
+                trigger: Trigger::Manual,
+                ..Default::default()
+            });
+            s
+        };
+        // 每分钟的快照, 以及 2 个很早的手动快照
+        let mut snapshot: Vec<Snapshot> = (0..30).map(|i| Snapshot::new_t(t - i * 60)).collect();
+        snapshot.push(manual(t - 86400));
+        snapshot.push(manual(t - 86400 * 2));
+
+        let timer = vec![PmbsConfigKeep::new_sn(600, 2)];
+        // 没有 manual 规则: 和 timer 快照一起清理
+        let Decision { keep, .. } = decide(&config(timer.clone()), snapshot.clone());
+        assert!(keep.iter().all(|x| x.get_class() == Trigger::Timer));
+
+        // manual 规则: 单独保留
+        let mut rule = timer.clone();
+        let mut m = PmbsConfigKeep::new_sn(60, 10);
+        m.class = Some(Trigger::Manual);
+        rule.push(m);
+        let Decision { keep, clean, .. } = decide(&config(rule), snapshot);
+        assert_eq!(
+            t_list(
+                &keep
+                    .iter()
+                    .filter(|x| x.get_class() == Trigger::Manual)
+                    .cloned()
+                    .collect::<Vec<_>>()
+            ),
+            vec![t - 86400, t - 86400 * 2]
+        );
+        assert!(clean.iter().all(|x| x.get_class() == Trigger::Timer));
+    }
+
+    /// 非默认的 keep_latest, keep_tolerance
+    #[test]
+    fn decide_keep_latest() {
+        let t = 1756392923;
+        // 每分钟的快照, 共 1 小时
+        let snapshot: Vec<Snapshot> = (0..60).map(|i| Snapshot::new_t(t - i * 60)).collect();
+        let rule = vec![PmbsConfigKeep::new_sn(600, 1)];
+        // 保留的快照: 距离最新快照的时间 (秒)
+        let ago = |c: &PmbsConfig| -> Vec<u64> {
+            let Decision { keep, .. } = decide(c, snapshot.clone());
+            keep.iter().map(|x| t - x.t).collect()
+        };
+
+        // 默认: 全部保留最新 5 分钟
+        assert_eq!(
+            ago(&config(rule.clone())),
+            vec![0, 60, 120, 180, 240, 300, 840]
+        );
+
+        // 全部保留最新 30 分钟
+        let mut c = config(rule.clone());
+        c.keep_latest_s = 1800;
+        let k = ago(&c);
+        assert_eq!(k.len(), 32);
+        assert_eq!(k[30..], [1800, 2340]);
+
+        // 容忍误差 90 秒: 6 分钟之前的快照, 也在全部保留的时间之内
+        let mut c = config(rule);
+        c.keep_tolerance_s = 90;
+        assert_eq!(ago(&c), vec![0, 60, 120, 180, 240, 300, 360, 840]);
+    }
+
+    /// 每个快照 保留/清理 的原因
+    #[test]
+    fn decide_reason() {
+        use crate::config::{Calendar, Pick};
+
+        let t = 1756392923;
+        // 每分钟的快照, 共 1 小时
+        let mut snapshot: Vec<Snapshot> = (0..60).map(|i| Snapshot::new_t(t - i * 60)).collect();
+        snapshot[10].pinned = true;
+        let rule = vec![
+            PmbsConfigKeep::new_sn(600, 2),
+            PmbsConfigKeep::new_calendar(Calendar::Yearly, 1, Pick::First),
+        ];
+        let d = decide(&config(rule), snapshot);
+        // 每个快照都有原因
+        assert_eq!(d.reason.len(), 60);
+        let r = |ago: u64| d.get_reason(t - ago * 60).unwrap().clone();
+
+        assert_eq!(r(0), Reason::Latest);
+        assert_eq!(r(5), Reason::Latest);
+        assert_eq!(r(10), Reason::Pinned);
+        // 第一个被遮盖区间 (5 分钟之后) 保留最后一条
+        assert_eq!(r(14), Reason::Rule { rule: 0, slot: 1 });
+        assert_eq!(r(6), Reason::Masked { by: t - 14 * 60 });
+        assert_eq!(r(24), Reason::Rule { rule: 0, slot: 2 });
+        assert_eq!(r(30), Reason::Exhausted);
+        // 最旧的快照: 日历规则保留
+        assert!(matches!(r(59), Reason::Calendar { rule: 1, .. }));
+        assert_eq!(r(14).describe(&config(Vec::new()).keep), "keep[0]  slot 1");
+    }
+
+    /// 规则的时间比 UNIX_EPOCH 以来的时间还长 (比如 `100y`): 不能溢出
+    #[test]
+    fn decide_long_rule() {
+        let t = 1756392923;
+        let s = crate::config::parse_time("100y").unwrap();
+        assert!(s > t);
+        let snapshot: Vec<Snapshot> = (0..10).map(|i| Snapshot::new_t(t - i * 3600)).collect();
+        let Decision { keep, clean, .. } =
+            decide(&config(vec![PmbsConfigKeep::new_sn(s, 2)]), snapshot);
+        // 全部在第一个遮盖区间内: 保留最新的, 以及区间内最旧的
+        assert_eq!(t_list(&keep), vec![t, t - 9 * 3600]);
+        assert_eq!(clean.len(), 8);
+    }
+
+    /// 间隔时间规则和日历规则同时使用
+    #[test]
+    fn decide_calendar() {
+        use crate::config::{Calendar, Pick};
+
+        // 2025-07-01T00:00:00Z, 每年一个快照 (年中, 不受时区影响)
+        let t = 1751328000;
+        let y = 365 * 86400;
+        let mut snapshot: Vec<Snapshot> = (0..6).map(|i| Snapshot::new_t(t - i * y)).collect();
+        snapshot[1].pinned = true;
+
+        let rule = vec![
+            PmbsConfigKeep::new_sn(600, 1),
+            PmbsConfigKeep::new_calendar(Calendar::Yearly, 3, Pick::Last),
+        ];
+        let Decision { keep, clean, .. } = decide(&config(rule.clone()), snapshot.clone());
+        assert_eq!(t_list(&keep), vec![t, t - y, t - 2 * y, t - 3 * y]);
+        assert_eq!(t_list(&clean), vec![t - 5 * y, t - 4 * y]);
+
+        let bucket = super::decide_calendar(&rule, snapshot);
+        assert_eq!(
+            bucket.iter().map(|x| x.0).collect::<Vec<_>>(),
+            vec![t, t - 2 * y, t - 3 * y]
+        );
+        assert_eq!(bucket[0].1, "yearly 2025");
+    }
 }
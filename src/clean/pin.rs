@@ -0,0 +1,139 @@
+//! 固定的快照 (/.pmbs/pin.json), 自动清理永远不会删除
+use std::{
+    fs::read_to_string,
+    io::ErrorKind,
+    os::unix::fs::MetadataExt,
+    path::{Path, PathBuf},
+};
+
+use log::{debug, error, warn};
+use serde::{Deserialize, Serialize};
+
+use crate::{error::PmbsResult, snapshot::SafeDir};
+
+/// 固定快照列表文件 /.pmbs/pin.json
+pub const FILE_PIN: &str = "pin.json";
+
+/// 一个固定的快照
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Pin {
+    /// 快照路径 (字符串 2025/1756392923 格式)
+    pub path: String,
+    /// 固定时间 (时间戳)
+    pub t: u64,
+    /// 固定截止时间 (时间戳), 没有表示永久固定
+    #[serde(default)]
+    pub until: Option<u64>,
+}
+
+impl Pin {
+    /// 在指定时间是否 (仍然) 有效
+    pub fn is_active(&self, now: u64) -> bool {
+        match self.until {
+            Some(until) => until > now,
+            None => true,
+        }
+    }
+}
+
+/// 固定快照列表
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PinList {
+    pub pin: Vec<Pin>,
+}
+
+impl PinList {
+    /// 检查快照是否被固定
+    pub fn is_pinned(&self, path: &str, now: u64) -> bool {
+        self.pin.iter().any(|x| x.path == path && x.is_active(now))
+    }
+
+    /// 固定快照 (替换之前的同一个快照)
+    pub fn add(&mut self, p: Pin) {
+        self.remove(&p.path);
+        self.pin.push(p);
+        self.pin.sort_by(|a, b| a.path.cmp(&b.path));
+    }
+
+    /// 取消固定, 返回: 是否存在
+    pub fn remove(&mut self, path: &str) -> bool {
+        let n = self.pin.len();
+        self.pin.retain(|x| x.path != path);
+        n != self.pin.len()
+    }
+}
+
+fn pin_path(dir: &Path) -> PathBuf {
+    let mut p = dir.to_path_buf();
+    p.push(FILE_PIN);
+    p
+}
+
+/// 读取固定快照列表 (dir 是 .pmbs 目录)
+///
+/// 文件的所有者必须和 .pmbs 目录相同 (root), 否则忽略, 避免普通用户固定快照.
+pub fn read_pin(dir: &Path) -> PinList {
+    let p = pin_path(dir);
+    let owner = |p: &Path| p.symlink_metadata().map(|m| m.uid()).ok();
+    match (owner(&p), owner(dir)) {
+        (Some(a), Some(b)) if a == b => {}
+        (Some(a), _) => {
+            warn!("ignore pin list, bad owner {}  {}", a, p.to_string_lossy());
+            return PinList::default();
+        }
+        (None, _) => {
+            return PinList::default();
+        }
+    }
+
+    match read_to_string(&p) {
+        Ok(s) => match serde_json::from_str(&s) {
+            Ok(l) => l,
+            Err(e) => {
+                error!("can not parse pin list {}  {:?}", p.to_string_lossy(), e);
+                PinList::default()
+            }
+        },
+        Err(e) if e.kind() == ErrorKind::NotFound => PinList::default(),
+        Err(e) => {
+            error!("can not read pin list {}  {:?}", p.to_string_lossy(), e);
+            PinList::default()
+        }
+    }
+}
+
+/// 写入固定快照列表 (write-replace, 不跟随符号链接)
+pub fn write_pin(dir: &SafeDir, list: &PinList) -> PmbsResult<()> {
+    debug!("write pin list {}", pin_path(&dir.path).to_string_lossy());
+
+    let text = serde_json::to_string_pretty(list).unwrap();
+    dir.write_file(FILE_PIN, text.as_bytes())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn pin_list() {
+        let mut l = PinList::default();
+        l.add(Pin {
+            path: "2025/1756392923".into(),
+            t: 1756392923,
+            until: None,
+        });
+        l.add(Pin {
+            path: "2025/1756390000".into(),
+            t: 1756392923,
+            until: Some(1756400000),
+        });
+        assert!(l.is_pinned("2025/1756392923", 1800000000));
+        assert!(l.is_pinned("2025/1756390000", 1756399999));
+        assert!(!l.is_pinned("2025/1756390000", 1756400000));
+        assert!(!l.is_pinned("2025/1756391111", 1756392923));
+
+        assert!(l.remove("2025/1756392923"));
+        assert!(!l.remove("2025/1756392923"));
+        assert!(!l.is_pinned("2025/1756392923", 1756392923));
+    }
+}
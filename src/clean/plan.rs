@@ -0,0 +1,41 @@
+//! 保留规则 保留/清理 快照的原因 (用于 pmbs config explain, 清理日志)
+use serde::{Deserialize, Serialize};
+
+use crate::config::PmbsConfigKeep;
+
+/// 保留规则 保留/清理 快照的原因
+///
+/// 规则序号 (`rule`) 是在配置文件 `[[keep]]` 中的序号, 从 0 开始.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum Reason {
+    /// 保留: 固定的快照
+    Pinned,
+    /// 保留: 最新快照之前 `keep_latest` 之内
+    Latest,
+    /// 保留: 间隔时间规则, 占用规则的第几个 (从 1 开始)
+    Rule { rule: usize, slot: u32 },
+    /// 保留: 日历规则, 以及时间段 (比如 `daily 2025-08-28`)
+    Calendar { rule: usize, bucket: String },
+    /// 清理: 被遮盖 (同一个间隔内保留了更旧的快照 `by`)
+    Masked { by: u64 },
+    /// 清理: 保留规则已用完
+    Exhausted,
+}
+
+impl Reason {
+    /// 显示原因, 比如 `keep[1] time = 1h  slot 3/24`
+    pub fn describe(&self, rule: &[PmbsConfigKeep]) -> String {
+        match self {
+            Self::Pinned => "pinned".into(),
+            Self::Latest => "keep_latest".into(),
+            Self::Rule { rule: i, slot } => match rule.get(*i) {
+                Some(r) => format!("keep[{}] time = {}  slot {}/{}", i, r.time, slot, r.n),
+                None => format!("keep[{}]  slot {}", i, slot),
+            },
+            Self::Calendar { rule: i, bucket } => format!("keep[{}] {}", i, bucket),
+            Self::Masked { by } => format!("masked by {}", by),
+            Self::Exhausted => "rules exhausted".into(),
+        }
+    }
+}
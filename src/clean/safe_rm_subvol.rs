@@ -1,10 +1,19 @@
 //! 安全删除快照 (btrfs subvol)
-use std::process::Command;
+use std::{
+    path::{Path, PathBuf},
+    str::FromStr,
+};
 
-use log::debug;
+use log::{debug, info, warn};
 use regex::Regex;
+use serde::{Deserialize, Serialize};
 
-use crate::{cli::sh_run, config::ConfigEnv};
+use crate::{
+    backend::{BACKEND_DIR, SnapshotBackend, SubvolInfo, get_backend},
+    config::{ConfigEnv, PmbsConfig, get_re_snapshot_dir},
+    error::{PmbsError, PmbsResult},
+    snapshot::{FileId, SafeDir, SnapshotMeta, read_meta_safe, rm_meta},
+};
 
 /// 检查要删除的 subvol 路径.
 ///
@@ -17,37 +26,295 @@ pub fn get_re_safe_check_path() -> Regex {
     Regex::new(r"/\.pmbs/[1-9][0-9]{3,}/[1-9][0-9]{9,}$").unwrap()
 }
 
+/// 配置了 snapshot_dir 时, 检查要删除的 subvol 路径.
+///
+/// 只允许 snapshot_dir 之下一层年目录中的快照, 比如
+/// `/.pmbs-store/home/2025/1756392923` (snapshot_dir 是 `/.pmbs-store/home`).
+/// snapshot_dir 本身的格式见 [`get_re_snapshot_dir`].
+pub fn get_re_safe_check_path_dir(dir: &str) -> Regex {
+    Regex::new(&format!(
+        r"^{}/[1-9][0-9]{{3,}}/[1-9][0-9]{{9,}}$",
+        regex::escape(dir)
+    ))
+    .unwrap()
+}
+
+/// 删除快照的选项
+#[derive(Debug, Clone, Default)]
+pub struct RmOpt {
+    /// 一个删除命令删除的快照个数, 0 表示每个快照一个命令
+    pub batch_size: usize,
+    /// 每批删除之后, 等待删除的快照释放存储空间
+    pub sync: bool,
+}
+
+impl RmOpt {
+    /// 使用配置中的 `delete_batch_size`, `delete_sync`
+    pub fn new(config: &PmbsConfig) -> Self {
+        Self {
+            batch_size: config.delete_batch_size.unwrap_or(0) as usize,
+            sync: config.delete_sync,
+        }
+    }
+}
+
+/// 删除一个快照的结果
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RmStatus {
+    /// 已删除
+    Deleted,
+    /// 删除失败
+    Failed,
+    /// 快照已经不存在, 跳过
+    Gone,
+}
+
+impl RmStatus {
+    /// 清理日志中的名称
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Deleted => "deleted",
+            Self::Failed => "failed",
+            Self::Gone => "gone",
+        }
+    }
+}
+
+/// 删除一个快照的结果 (含错误信息)
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RmResult {
+    /// 结果
+    pub status: RmStatus,
+    /// 删除失败: 命令的退出码
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub code: Option<i32>,
+    /// 删除失败: 错误信息
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+impl RmResult {
+    fn ok(status: RmStatus) -> Self {
+        Self {
+            status,
+            code: None,
+            error: None,
+        }
+    }
+
+    fn failed(e: &PmbsError) -> Self {
+        Self {
+            status: RmStatus::Failed,
+            code: match e {
+                PmbsError::Command(_, code) => Some(*code),
+                _ => None,
+            },
+            error: Some(e.to_string()),
+        }
+    }
+}
+
+/// 检查快照的 parent UUID: 是配置的 subvol (`parent`), 或者和创建快照时记录在元数据中的相同
+///
+/// 回滚 (用快照替换 subvol) 之后, subvol 的 UUID 改变, 之前的快照的 parent UUID
+/// 不再是当前的 subvol. 但是快照本身的 UUID 和 parent UUID 仍然和元数据中记录的相同.
+fn check_parent(i: &SubvolInfo, parent: &str, meta: Option<&SnapshotMeta>) -> bool {
+    if i.parent_uuid.as_deref() == Some(parent) {
+        return true;
+    }
+    meta.is_some_and(|m| {
+        m.uuid.as_deref() == Some(i.uuid.as_str())
+            && m.parent_uuid.is_some()
+            && m.parent_uuid == i.parent_uuid
+    })
+}
+
+/// 删除之前检查一个快照 (不跟随符号链接), 返回 None 表示快照已经不存在
+///
+/// btrfs: 必须是只读的 subvol, 并且 parent UUID 是配置的 subvol (`parent`)
+/// (见 [`check_parent`], 配置 `skip_parent_check` 时不检查).
+fn check_snapshot(
+    b: &dyn SnapshotBackend,
+    pmbs: &SafeDir,
+    parent: Option<&str>,
+    skip_parent: bool,
+    p: &Path,
+    t: u64,
+) -> PmbsResult<Option<FileId>> {
+    // 路径已经检查过格式: .pmbs/2025/1756392923
+    let name = |p: &Path| {
+        p.file_name()
+            .unwrap_or_default()
+            .to_string_lossy()
+            .to_string()
+    };
+    let year = match p.parent().map(|x| pmbs.open_dir(&name(x), false)) {
+        Some(r) => r?,
+        None => None,
+    };
+    let id = match year {
+        Some(y) => y.stat_dir(&name(p))?,
+        None => None,
+    };
+    if let (Some(_), Some(parent)) = (id, parent) {
+        let i = b.show(p)?;
+        if !i.readonly {
+            return Err(PmbsError::Safety(format!(
+                "not readonly  {}",
+                p.to_string_lossy()
+            )));
+        }
+        if !skip_parent && !check_parent(&i, parent, read_meta_safe(pmbs, t).as_ref()) {
+            return Err(PmbsError::Safety(format!(
+                "bad parent uuid  {}  {:?}",
+                p.to_string_lossy(),
+                i.parent_uuid
+            )));
+        }
+    }
+    Ok(id)
+}
+
 /// 删除 subvol (列表)
 ///
-/// ## panic
+/// 先检查全部路径, 然后分批删除. 删除失败时继续删除后面的快照.
+/// 一批删除失败时, 逐个重试这一批快照.
+///
+/// 每批删除之前检查快照 (不跟随符号链接, 只读, parent UUID),
+/// 未通过检查的快照不删除, 结果为失败.
+///
+/// 返回: 每个快照的结果 (时间戳, 结果)
+///
+/// ## 错误
 ///
-/// + 如果有路径未通过检查
-/// + 如果执行删除命令失败
-pub fn safe_rm_subvol_list(config: &ConfigEnv, list: Vec<String>) {
-    let re = get_re_safe_check_path();
+/// + [`PmbsError::Safety`]: 如果有路径未通过检查, 或者不在快照保存目录之中,
+///   或者快照保存目录未通过检查 (符号链接, 所有者) (不删除任何快照)
+pub fn safe_rm_subvol_list(
+    c: &ConfigEnv,
+    config: &PmbsConfig,
+    list: Vec<String>,
+    opt: &RmOpt,
+) -> PmbsResult<Vec<(u64, RmResult)>> {
+    let re = match &config.snapshot_dir {
+        Some(d) if get_re_snapshot_dir().is_match(d) => get_re_safe_check_path_dir(d),
+        Some(d) => {
+            return Err(PmbsError::Safety(format!("bad snapshot_dir {}", d)));
+        }
+        None => get_re_safe_check_path(),
+    };
+    let b = get_backend(c);
+    let dir = config.dir_pmbs();
+    let dir = dir.as_path();
 
+    // 检查全部路径: (路径, 时间戳)
+    let mut checked: Vec<(PathBuf, u64)> = Vec::new();
     for i in list {
         debug!("check {}", i);
 
-        if re.is_match(&i) {
-            // 检查通过, 可以删除
+        let p = Path::new(&i);
+        let t = p
+            .file_name()
+            .and_then(|x| u64::from_str(&x.to_string_lossy()).ok());
+        match t {
+            Some(t) if re.is_match(&i) && p.parent().and_then(|x| x.parent()) == Some(dir) => {
+                // 检查通过, 可以删除
+                checked.push((p.to_path_buf(), t));
+            }
+            _ => {
+                // 错误路径 !
+                return Err(PmbsError::Safety(format!("bad subvol path {}", i)));
+            }
+        }
+    }
+
+    // 检查快照保存目录, 以及 subvol 的 UUID (普通目录模拟的快照没有 UUID)
+    let pmbs = SafeDir::open_pmbs(dir, false)?;
+    let parent = match c.backend.as_str() {
+        BACKEND_DIR => None,
+        _ => Some(b.show(Path::new(&config.subvol))?.uuid),
+    };
 
-            // 执行命令, 比如 btrfs subvol delete /home/.pmbs/2025/1756392923
-            let mut c = Command::new(config.bin_btrfs.clone());
-            c.arg("subvol").arg("delete").arg(&i);
-            let code = sh_run(c);
-            if 0 != code {
-                panic!("can not rm subvol {}", i);
+    // 快照是否存在
+    let exists = |p: &Path| p.symlink_metadata().is_ok();
+    let mut o: Vec<(u64, RmResult)> = Vec::new();
+    let total = checked.len();
+    for (i, batch) in checked.chunks(opt.batch_size.max(1)).enumerate() {
+        // 等待上一批删除的快照释放存储空间
+        if opt.sync && i > 0 {
+            debug!("sync {}", dir.to_string_lossy());
+            if let Err(e) = b.sync(dir) {
+                warn!("sync failed  {}", e);
+            }
+        }
+        // 已经不存在的快照: 跳过, 未通过检查的快照: 失败
+        let mut ok: Vec<(&PathBuf, &u64, FileId)> = Vec::new();
+        for (p, t) in batch {
+            // 执行命令之前, 再次检查路径没有被替换
+            let skip = config.skip_parent_check;
+            let r = check_snapshot(b.as_ref(), &pmbs, parent.as_deref(), skip, p, *t)
+                .and_then(|id| id.map(|x| x.check_same(p).map(|_| x)).transpose());
+            match r {
+                Ok(Some(id)) => ok.push((p, t, id)),
+                Ok(None) => {
+                    warn!("snapshot gone, skip  {}", p.to_string_lossy());
+                    rm_meta(&pmbs, *t);
+                    o.push((*t, RmResult::ok(RmStatus::Gone)));
+                }
+                Err(e) => {
+                    warn!("refuse to delete  {}", e);
+                    o.push((*t, RmResult::failed(&e)));
+                }
             }
-        } else {
-            // 错误路径 !
-            panic!("bad subvol path {}", i);
+        }
+        let batch = ok;
+
+        // 比如 btrfs subvol delete /home/.pmbs/2025/1756392923
+        let p: Vec<PathBuf> = batch.iter().map(|x| x.0.clone()).collect();
+        let r = match p.len() {
+            0 => continue,
+            1 => b.delete(&p[0]),
+            _ => b.delete_batch(&p),
+        };
+        let single = p.len() == 1;
+        for (p, t, id) in batch {
+            let r = match &r {
+                Ok(()) => Ok(()),
+                Err(e) if single => Err(RmResult::failed(e)),
+                // 一批删除失败: 已经删除的快照不再重试, 其余的逐个重试
+                Err(_) if !exists(p) => Ok(()),
+                Err(_) => id
+                    .check_same(p)
+                    .and_then(|_| b.delete(p))
+                    .map_err(|e| RmResult::failed(&e)),
+            };
+            let r = match r {
+                Ok(()) => {
+                    // 删除快照元数据: /.pmbs/2025/1756392923 -> /.pmbs/meta/1756392923.json
+                    rm_meta(&pmbs, *t);
+                    RmResult::ok(RmStatus::Deleted)
+                }
+                Err(r) => {
+                    warn!(
+                        "delete failed  {}  {}",
+                        p.to_string_lossy(),
+                        r.error.as_deref().unwrap_or("")
+                    );
+                    r
+                }
+            };
+            o.push((*t, r));
+        }
+        if opt.batch_size > 0 {
+            info!("delete {}/{}  {}", o.len(), total, dir.to_string_lossy());
         }
     }
+    Ok(o)
 }
 
 /// 对正则表达式匹配进行测试
 #[cfg(test)]
+#[allow(clippy::bool_assert_comparison)]
 mod test_re {
     use super::*;
 
@@ -63,6 +330,38 @@ mod test_re {
         assert_eq!(re.is_match("/.pmbs/10000/20000000000"), true);
     }
 
+    // 正常匹配: snapshot_dir
+    #[test]
+    fn re_should_match_snapshot_dir() {
+        let re = get_re_safe_check_path_dir("/.pmbs-store/home");
+
+        assert_eq!(re.is_match("/.pmbs-store/home/2025/1756392923"), true);
+        assert_eq!(re.is_match("/.pmbs-store/home/10000/20000000000"), true);
+        let re = get_re_safe_check_path_dir("/mnt/.pmbs-store/a.b");
+        assert_eq!(re.is_match("/mnt/.pmbs-store/a.b/2025/1756392923"), true);
+    }
+
+    // 不匹配: snapshot_dir (只允许这个目录之下的快照)
+    #[test]
+    fn re_not_match_snapshot_dir() {
+        let re = get_re_safe_check_path_dir("/.pmbs-store/home");
+
+        assert_eq!(re.is_match("/.pmbs-store/home/x/2025/1756392923"), false);
+        assert_eq!(re.is_match("/.pmbs-store/home2/2025/1756392923"), false);
+        assert_eq!(re.is_match("/.pmbs-store/2025/1756392923"), false);
+        assert_eq!(re.is_match("/mnt/.pmbs-store/home/2025/1756392923"), false);
+        assert_eq!(re.is_match("/.pmbs-store/home/2025"), false);
+        assert_eq!(re.is_match("/.pmbs-store/home/2025/1756392923/x"), false);
+        assert_eq!(re.is_match("/home/.pmbs/2025/1756392923"), false);
+        let re = get_re_safe_check_path_dir("/mnt/.pmbs-store/a.b");
+        assert_eq!(re.is_match("/mnt/.pmbs-store/axb/2025/1756392923"), false);
+
+        // 默认格式不匹配 snapshot_dir
+        let re = get_re_safe_check_path();
+        assert_eq!(re.is_match("/.pmbs-store/home/2025/1756392923"), false);
+        assert_eq!(re.is_match("/.pmbs/home/2025/1756392923"), false);
+    }
+
     // 不匹配: 空
     #[test]
     fn re_not_match_empty() {
@@ -135,3 +434,50 @@ mod test_re {
         assert_eq!(re.is_match("/apmbs/2025/1756392923"), false);
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// 回滚之后 subvol 的 UUID 改变: 接受元数据中记录的 UUID
+    #[test]
+    fn parent_uuid() {
+        let i = SubvolInfo {
+            uuid: "snap-1".into(),
+            parent_uuid: Some("home-1".into()),
+            readonly: true,
+            ..Default::default()
+        };
+        let meta = |uuid: &str, parent: Option<&str>| SnapshotMeta {
+            uuid: Some(uuid.into()),
+            parent_uuid: parent.map(|x| x.into()),
+            ..Default::default()
+        };
+        assert!(check_parent(&i, "home-1", None));
+        // 回滚之后: 当前 subvol 是 home-2
+        assert!(!check_parent(&i, "home-2", None));
+        assert!(check_parent(
+            &i,
+            "home-2",
+            Some(&meta("snap-1", Some("home-1")))
+        ));
+        // 元数据和快照不符 (或者没有记录 UUID)
+        assert!(!check_parent(
+            &i,
+            "home-2",
+            Some(&meta("snap-2", Some("home-1")))
+        ));
+        assert!(!check_parent(
+            &i,
+            "home-2",
+            Some(&meta("snap-1", Some("other")))
+        ));
+        assert!(!check_parent(&i, "home-2", Some(&SnapshotMeta::default())));
+        // 不是快照 (没有 parent UUID)
+        let i = SubvolInfo {
+            parent_uuid: None,
+            ..i
+        };
+        assert!(!check_parent(&i, "home-2", Some(&meta("snap-1", None))));
+    }
+}
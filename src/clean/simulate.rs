@@ -0,0 +1,207 @@
+//! 模拟自动清理: 在模拟的时间线上, 反复执行保留规则
+//!
+//! 用于检查新的保留规则, 而不必等待几天, 观察实际的清理结果.
+use log::debug;
+
+use crate::{
+    config::PmbsConfig,
+    error::{PmbsError, PmbsResult},
+    snapshot::in_interval,
+};
+
+use super::{Snapshot, budget::age_tier, decide};
+
+/// 模拟参数 (时间单位: 秒)
+#[derive(Debug, Clone)]
+pub struct SimulateOpt {
+    /// 模拟的结束时间
+    pub end: u64,
+    /// 模拟的天数
+    pub days: u64,
+    /// 创建快照的间隔时间 (timer)
+    pub interval: u64,
+    /// 创建快照时间的随机延迟 (最大值)
+    pub jitter: u64,
+    /// 不创建快照的时间段 (比如关机): (开始时间, 持续时间), 开始时间相对于模拟开始
+    pub gaps: Vec<(u64, u64)>,
+    /// 执行自动清理的间隔时间
+    pub clean_every: u64,
+    /// 随机数种子
+    pub seed: u64,
+}
+
+impl Default for SimulateOpt {
+    fn default() -> Self {
+        Self {
+            end: 0,
+            days: 30,
+            interval: 60,
+            jitter: 0,
+            gaps: Vec::new(),
+            clean_every: 600,
+            seed: 1,
+        }
+    }
+}
+
+/// 模拟结果
+#[derive(Debug, Clone, Default)]
+pub struct SimulateReport {
+    /// 模拟的结束时间
+    pub end: u64,
+    /// 总共创建的快照个数
+    pub made: usize,
+    /// 最后保留的快照时间戳 (按时间降序)
+    pub keep: Vec<u64>,
+    /// 每天结束时的快照个数: (第几天, 快照个数)
+    pub count: Vec<(u64, usize)>,
+    /// 快照个数的最大值
+    pub peak: usize,
+}
+
+impl SimulateReport {
+    /// 每个年龄分段的快照个数: (分段名称, 快照个数)
+    pub fn age_band(&self) -> Vec<(String, usize)> {
+        age_tier()
+            .into_iter()
+            .map(|(lo, hi, name)| {
+                let n = self
+                    .keep
+                    .iter()
+                    .filter(|t| (lo..hi).contains(&self.end.saturating_sub(**t)))
+                    .count();
+                (name, n)
+            })
+            .collect()
+    }
+
+    /// 相邻两个快照之间的最大间隔: (间隔秒数, 较旧的快照, 较新的快照)
+    pub fn max_gap(&self) -> Option<(u64, u64, u64)> {
+        self.keep
+            .windows(2)
+            .map(|w| (w[0] - w[1], w[1], w[0]))
+            .max_by_key(|x| x.0)
+    }
+}
+
+/// 线性同余随机数生成器 (结果可重复)
+struct Lcg(u64);
+
+impl Lcg {
+    fn next(&mut self) -> u64 {
+        self.0 = self
+            .0
+            .wrapping_mul(6364136223846793005)
+            .wrapping_add(1442695040888963407);
+        self.0 >> 33
+    }
+}
+
+/// 执行模拟: 按照 timer 创建快照, 并且每隔 `clean_every` 执行一次自动清理
+pub fn simulate(config: &PmbsConfig, opt: &SimulateOpt) -> PmbsResult<SimulateReport> {
+    let days = opt
+        .days
+        .checked_mul(86400)
+        .ok_or_else(|| PmbsError::Arg(format!("bad days  {}", opt.days)))?;
+    let start = opt.end.saturating_sub(days);
+    let interval = opt.interval.max(1);
+    let clean_every = opt.clean_every.max(1);
+    let mut rng = Lcg(opt.seed);
+    debug!("simulate  {:?}", opt);
+
+    let mut r = SimulateReport {
+        end: opt.end,
+        ..Default::default()
+    };
+    let mut list: Vec<Snapshot> = Vec::new();
+    let mut latest: Option<u64> = None;
+    // 下次自动清理的时间, 下次统计快照个数的时间
+    let mut next_clean = start + clean_every;
+    let mut next_day = start + 86400;
+
+    let mut tick = start;
+    while tick <= opt.end {
+        // 到了执行自动清理的时间
+        while next_clean <= tick {
+            list = decide(config, list).keep;
+            r.peak = r.peak.max(list.len());
+            if next_day <= next_clean {
+                r.count.push(((next_day - start) / 86400, list.len()));
+                next_day += 86400;
+            }
+            next_clean += clean_every;
+        }
+
+        let t = tick + rng.next() % (opt.jitter + 1);
+        tick += interval;
+        // 不创建快照的时间段
+        if opt
+            .gaps
+            .iter()
+            .map(|(s, d)| start.saturating_add(*s)..start.saturating_add(*s).saturating_add(*d))
+            .any(|x| x.contains(&t))
+        {
+            continue;
+        }
+        // 距离上一个快照不到 interval (配置文件)
+        if let Some(l) = latest
+            && (t <= l || in_interval(l, config.interval_s, t))
+        {
+            continue;
+        }
+        list.push(Snapshot::new_t(t));
+        latest = Some(t);
+        r.made += 1;
+        r.peak = r.peak.max(list.len());
+    }
+    list = decide(config, list).keep;
+    r.keep = list.iter().map(|x| x.t).collect();
+    Ok(r)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::config::PmbsConfigKeep;
+
+    #[test]
+    fn simulate_1d() {
+        let config = PmbsConfig {
+            keep: vec![
+                PmbsConfigKeep::new_sn(60, 60),
+                PmbsConfigKeep::new_sn(3600, 24),
+            ],
+            ..PmbsConfig::new_subvol("/")
+        };
+        let opt = SimulateOpt {
+            end: 1756392923,
+            days: 2,
+            jitter: 2,
+            // 第 2 天, 关机 6 小时
+            gaps: vec![(86400 + 3600, 6 * 3600)],
+            ..Default::default()
+        };
+        let r = simulate(&config, &opt).unwrap();
+        assert_eq!(r.made, 2 * 1440 + 1 - 360);
+        assert_eq!(r.count.len(), 2);
+        // 保留规则: 5 分钟内全部保留, 加上最多 60 + 24 个
+        assert!(r.keep.len() <= 5 + 1 + 60 + 24);
+        assert!(r.keep.len() > 60);
+        // 关机 6 小时, 就是最大间隔
+        let (gap, _, _) = r.max_gap().unwrap();
+        assert!(gap >= 6 * 3600);
+        assert_eq!(
+            r.age_band().iter().map(|x| x.1).sum::<usize>(),
+            r.keep.len()
+        );
+        // 结果可重复
+        assert_eq!(simulate(&config, &opt).unwrap().keep, r.keep);
+
+        // 天数溢出
+        let opt = SimulateOpt {
+            days: u64::MAX / 1000,
+            ..opt
+        };
+        assert!(matches!(simulate(&config, &opt), Err(PmbsError::Arg(_))));
+    }
+}
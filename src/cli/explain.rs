@@ -0,0 +1,70 @@
+//! pmbs config explain: 显示每个快照 保留/清理 的原因
+use std::cmp::Reverse;
+
+use crate::{
+    clean::Snapshot,
+    error::{PmbsError, PmbsResult},
+    snapshot::{format_t_local, parse_snapshot_t},
+};
+
+use super::{get_clean, help::bad_cli_arg};
+
+/// pmbs config explain PATH [SNAPSHOT]
+pub fn c_config_explain(a: Vec<String>) -> PmbsResult<()> {
+    // 解析命令行参数
+    let (path, name) = match a.len() {
+        1 => (&a[0], None),
+        2 => (&a[0], Some(&a[1])),
+        _ => {
+            return Err(bad_cli_arg());
+        }
+    };
+    // 快照, 接受 `2025/1756392923` 或者 `1756392923`
+    let t = match name {
+        Some(name) => match parse_snapshot_t(name) {
+            Some(t) => Some(t),
+            None => {
+                return Err(PmbsError::Arg(format!("bad snapshot  {}", name)));
+            }
+        },
+        None => None,
+    };
+
+    let (config, d) = get_clean(path)?;
+    let rule = &config.config.keep;
+    // 全部快照 (按时间降序): (快照, 是否保留)
+    let mut list: Vec<(&Snapshot, bool)> = d
+        .keep
+        .iter()
+        .map(|x| (x, true))
+        .chain(d.clean.iter().map(|x| (x, false)))
+        .filter(|(x, _)| t.is_none() || t == Some(x.t))
+        .collect();
+    list.sort_by_key(|(x, _)| Reverse(x.t));
+    if list.is_empty()
+        && let Some(name) = name
+    {
+        return Err(PmbsError::Arg(format!("snapshot not found  {}", name)));
+    }
+
+    for (i, keep) in list {
+        let reason = match d.get_reason(i.t) {
+            Some(r) => r.describe(rule),
+            None => "-".into(),
+        };
+        // 全局限制强制 保留/清理
+        let force = match d.get_force(i.t) {
+            Some(f) => format!("  *{}", f.as_str()),
+            None => String::new(),
+        };
+        println!(
+            "{} {}  {}  {}{}",
+            if keep { "keep" } else { "clean" },
+            i.path,
+            format_t_local(i.t),
+            reason,
+            force
+        );
+    }
+    Ok(())
+}
@@ -0,0 +1,428 @@
+//! pmbs find: 列出一个文件在所有快照中的不同版本
+use std::{
+    cmp::Reverse,
+    env,
+    fs::{File, Metadata},
+    io::{self, Read},
+    os::unix::fs::MetadataExt,
+    path::{Component, Path, PathBuf},
+};
+
+use log::{debug, warn};
+use serde::Serialize;
+
+use crate::{
+    clean::ls_snapshot,
+    config::{ConfigEnv, PmbsConfig, get_env_config, read_all_config},
+    error::{PmbsError, PmbsResult},
+    snapshot::{DIR_PMBS, format_t_local},
+};
+
+use super::{
+    help::bad_cli_arg,
+    output::{is_json, print_json},
+};
+
+/// 文件在一个快照中的状态 (相同的状态认为是同一个版本)
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+struct FileState {
+    /// `file`, `dir`, `symlink`, `other`
+    kind: &'static str,
+    /// 文件大小 (字节)
+    size: u64,
+    /// 修改时间 (UNIX_EPOCH 开始的秒数)
+    mtime: i64,
+    /// 修改时间 (纳秒部分)
+    mtime_ns: i64,
+    /// 文件内容的 hash (--hash): FNV-1a 64 位, 16 个十六进制字符.
+    /// 只用于比较, 不是密码学 hash.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    hash: Option<String>,
+}
+
+/// FNV-1a 64 位: 初始值
+const FNV_OFFSET: u64 = 0xcbf2_9ce4_8422_2325;
+/// FNV-1a 64 位: 乘数
+const FNV_PRIME: u64 = 0x0000_0100_0000_01b3;
+
+/// FNV-1a 64 位 hash (算法固定, 输出在不同版本之间保持稳定)
+fn fnv1a(h: u64, data: &[u8]) -> u64 {
+    data.iter()
+        .fold(h, |h, b| (h ^ u64::from(*b)).wrapping_mul(FNV_PRIME))
+}
+
+/// 计算文件内容的 hash
+fn hash_file(p: &Path) -> io::Result<String> {
+    let mut f = File::open(p)?;
+    let mut h = FNV_OFFSET;
+    let mut buf = vec![0; 64 * 1024];
+    loop {
+        let n = f.read(&mut buf)?;
+        if n == 0 {
+            break;
+        }
+        h = fnv1a(h, &buf[..n]);
+    }
+    Ok(format!("{:016x}", h))
+}
+
+impl FileState {
+    fn new(m: &Metadata) -> Self {
+        let t = m.file_type();
+        let kind = if t.is_symlink() {
+            "symlink"
+        } else if t.is_dir() {
+            "dir"
+        } else if t.is_file() {
+            "file"
+        } else {
+            "other"
+        };
+        Self {
+            kind,
+            size: m.len(),
+            mtime: m.mtime(),
+            mtime_ns: m.mtime_nsec(),
+            hash: None,
+        }
+    }
+
+    /// 读取文件状态 (不跟随符号链接), 文件不存在时返回 None
+    fn read(p: &Path, hash: bool) -> io::Result<Option<Self>> {
+        let m = match p.symlink_metadata() {
+            Ok(m) => m,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                return Ok(None);
+            }
+            Err(e) => {
+                return Err(e);
+            }
+        };
+        let mut s = Self::new(&m);
+        if hash && m.is_file() {
+            s.hash = Some(hash_file(p)?);
+        }
+        Ok(Some(s))
+    }
+
+    /// 读取快照中的文件状态: 逐级检查上级目录 (不跟随符号链接).
+    ///
+    /// 上级目录是符号链接 (或者不是目录) 时, 认为文件不存在,
+    /// 避免跟随符号链接读取到快照之外的文件.
+    fn read_in(root: &Path, rel: &Path, hash: bool) -> io::Result<Option<Self>> {
+        let mut p = root.to_path_buf();
+        let mut it = rel.components().peekable();
+        while let Some(c) = it.next() {
+            let Component::Normal(name) = c else {
+                return Ok(None);
+            };
+            p.push(name);
+            if it.peek().is_none() {
+                break;
+            }
+            match p.symlink_metadata() {
+                Ok(m) if m.is_dir() => {}
+                Ok(_) => {
+                    return Ok(None);
+                }
+                Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                    return Ok(None);
+                }
+                Err(e) => {
+                    return Err(e);
+                }
+            }
+        }
+        Self::read(&p, hash)
+    }
+}
+
+/// 文件的一个版本: 连续的快照中状态相同
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+struct FileVersion {
+    /// 文件状态, None 表示文件不存在
+    state: Option<FileState>,
+    /// 最早的快照时间戳
+    from: u64,
+    /// 最晚的快照时间戳
+    to: u64,
+    /// 快照个数
+    count: usize,
+    /// 最晚的快照中的文件路径
+    file: String,
+    /// 和当前的文件相同
+    current: bool,
+}
+
+/// 合并状态相同的连续快照
+///
+/// `list`: (快照时间戳, 快照中的文件路径, 文件状态), 按时间升序
+fn collapse(list: Vec<(u64, String, Option<FileState>)>) -> Vec<FileVersion> {
+    let mut o: Vec<FileVersion> = Vec::new();
+    for (t, file, state) in list {
+        if let Some(v) = o.last_mut()
+            && v.state == state
+        {
+            v.to = t;
+            v.count += 1;
+            v.file = file;
+            continue;
+        }
+        o.push(FileVersion {
+            state,
+            from: t,
+            to: t,
+            count: 1,
+            file,
+            current: false,
+        });
+    }
+    o
+}
+
+/// 绝对路径 (文件可能已经被删除: 规范化存在的最长的上级目录)
+///
+/// 最后一级不跟随符号链接.
+fn abs_path(p: &str) -> PmbsResult<PathBuf> {
+    let p = Path::new(p);
+    let p = if p.is_absolute() {
+        p.to_path_buf()
+    } else {
+        env::current_dir().map_err(PmbsError::io("."))?.join(p)
+    };
+    let mut rest = Vec::new();
+    let mut cur = p.as_path();
+    loop {
+        match (cur.parent(), cur.file_name()) {
+            (Some(parent), Some(name)) => {
+                rest.push(name.to_os_string());
+                cur = parent;
+            }
+            _ => {
+                return Ok(p);
+            }
+        }
+        if let Ok(mut o) = cur.canonicalize() {
+            for i in rest.iter().rev() {
+                o.push(i);
+            }
+            return Ok(o);
+        }
+    }
+}
+
+/// 查找文件所在 subvol 的配置: 配置文件中最长的 subvol 前缀,
+/// 否则向上查找含有 `.pmbs` 目录的 subvol (默认配置).
+///
+/// 返回: (配置, subvol 规范化的路径)
+fn find_subvol(c: &ConfigEnv, p: &Path) -> PmbsResult<Option<(PmbsConfig, PathBuf)>> {
+    let mut best: Option<(PmbsConfig, PathBuf)> = None;
+    // 普通用户可能无法读取配置文件, 忽略没有权限的
+    for f in read_all_config(c, true)? {
+        if let Ok(s) = Path::new(&f.config.subvol).canonicalize()
+            && p.starts_with(&s)
+            && best.as_ref().is_none_or(|x| s.starts_with(&x.1))
+        {
+            debug!("found config {}  {}", f.path, s.to_string_lossy());
+            best = Some((f.config, s));
+        }
+    }
+    if best.is_some() {
+        return Ok(best);
+    }
+    Ok(p.ancestors()
+        .skip(1)
+        .find(|x| x.join(DIR_PMBS).is_dir())
+        .map(|x| {
+            (
+                PmbsConfig::new_subvol(&x.to_string_lossy()),
+                x.to_path_buf(),
+            )
+        }))
+}
+
+/// 显示文件状态
+fn state_str(s: &Option<FileState>) -> String {
+    match s {
+        Some(s) => {
+            let hash = match &s.hash {
+                Some(h) => format!("  {}", h),
+                None => String::new(),
+            };
+            format!(
+                "{}  {}  {}{}",
+                s.kind,
+                s.size,
+                format_t_local(s.mtime.max(0) as u64),
+                hash
+            )
+        }
+        None => "not exist".into(),
+    }
+}
+
+/// 输出 (--json)
+#[derive(Serialize)]
+struct FindOutput<'a> {
+    /// 文件 (绝对路径)
+    path: String,
+    /// subvol 路径
+    subvol: String,
+    /// 文件在 subvol 中的相对路径
+    rel: String,
+    /// 当前的文件状态
+    current: &'a Option<FileState>,
+    /// 全部版本 (按时间降序)
+    item: &'a [FileVersion],
+}
+
+/// pmbs find PATH [--hash]
+pub fn c_find(a: Vec<String>) -> PmbsResult<()> {
+    // 解析命令行参数
+    let hash = match a.len() {
+        1 => false,
+        2 if a[1] == "--hash" => true,
+        _ => {
+            return Err(bad_cli_arg());
+        }
+    };
+    let c = get_env_config();
+    let path = abs_path(&a[0])?;
+    let (config, subvol) = find_subvol(&c, &path)?.ok_or_else(|| {
+        PmbsError::Arg(format!("no snapshot found for  {}", path.to_string_lossy()))
+    })?;
+    // 前面已经检查过前缀
+    let rel = path.strip_prefix(&subvol).unwrap().to_path_buf();
+    debug!(
+        "find {}  in {}",
+        rel.to_string_lossy(),
+        subvol.to_string_lossy()
+    );
+
+    // 每个快照中的文件 (按时间升序)
+    let mut snapshot = ls_snapshot(&config.dir_pmbs())?;
+    snapshot.sort_by_key(|x| x.t);
+    let mut list = Vec::new();
+    for s in &snapshot {
+        let p = s.p.join(&rel);
+        match FileState::read_in(&s.p, &rel, hash) {
+            Ok(state) => list.push((s.t, p.to_string_lossy().to_string(), state)),
+            // 比如没有权限: 跳过这个快照
+            Err(e) => warn!("skip  {}  {}", p.to_string_lossy(), e),
+        }
+    }
+    let mut v = collapse(list);
+    // 和当前的文件比较
+    let current = FileState::read(&path, hash).map_err(PmbsError::io(&path))?;
+    for i in v.iter_mut() {
+        i.current = current.is_some() && i.state == current;
+    }
+    v.sort_by_key(|x| Reverse(x.to));
+
+    if is_json(&c) {
+        let o = FindOutput {
+            path: path.to_string_lossy().to_string(),
+            subvol: subvol.to_string_lossy().to_string(),
+            rel: rel.to_string_lossy().to_string(),
+            current: &current,
+            item: &v,
+        };
+        print_json("find", o);
+        return Ok(());
+    }
+    for i in &v {
+        let current = if i.current { "\t*current" } else { "" };
+        println!(
+            "{}\t{} ~ {}\t{}\t{}{}",
+            if i.state.is_some() { &i.file } else { "-" },
+            format_t_local(i.from),
+            format_t_local(i.to),
+            i.count,
+            state_str(&i.state),
+            current
+        );
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        fs::{create_dir_all, write},
+        os::unix::fs::symlink,
+    };
+
+    use super::*;
+    use crate::test_util::TmpDir;
+
+    fn state(size: u64, mtime: i64) -> Option<FileState> {
+        Some(FileState {
+            kind: "file",
+            size,
+            mtime,
+            mtime_ns: 0,
+            hash: None,
+        })
+    }
+
+    #[test]
+    fn collapse_version() {
+        let list = vec![
+            (1, "1/a".into(), None),
+            (2, "2/a".into(), state(10, 100)),
+            (3, "3/a".into(), state(10, 100)),
+            (4, "4/a".into(), state(12, 200)),
+            (5, "5/a".into(), None),
+            (6, "6/a".into(), state(12, 200)),
+        ];
+        let v = collapse(list);
+        let r: Vec<(u64, u64, usize, &str)> = v
+            .iter()
+            .map(|x| (x.from, x.to, x.count, x.file.as_str()))
+            .collect();
+        assert_eq!(
+            r,
+            vec![
+                (1, 1, 1, "1/a"),
+                (2, 3, 2, "3/a"),
+                (4, 4, 1, "4/a"),
+                (5, 5, 1, "5/a"),
+                (6, 6, 1, "6/a"),
+            ]
+        );
+        assert_eq!(v[1].state, state(10, 100));
+        assert_eq!(v[3].state, None);
+    }
+
+    /// FNV-1a 64 位的标准测试值
+    #[test]
+    fn hash() {
+        assert_eq!(fnv1a(FNV_OFFSET, b""), 0xcbf29ce484222325);
+        assert_eq!(fnv1a(FNV_OFFSET, b"a"), 0xaf63dc4c8601ec8c);
+        assert_eq!(fnv1a(FNV_OFFSET, b"foobar"), 0x85944171f73967e8);
+        // 分块计算, 结果相同
+        assert_eq!(fnv1a(fnv1a(FNV_OFFSET, b"foo"), b"bar"), 0x85944171f73967e8);
+    }
+
+    /// 快照中的上级目录是符号链接: 不跟随, 认为文件不存在
+    #[test]
+    fn symlink_parent() {
+        let tmp = TmpDir::new("find-symlink");
+        let snapshot = tmp.join("snapshot");
+        create_dir_all(snapshot.join("real")).unwrap();
+        write(snapshot.join("real/a"), "a").unwrap();
+        create_dir_all(tmp.join("outside")).unwrap();
+        write(tmp.join("outside/a"), "outside").unwrap();
+        symlink(tmp.join("outside"), snapshot.join("link")).unwrap();
+        symlink("a", snapshot.join("real/b")).unwrap();
+
+        let read = |rel: &str| FileState::read_in(&snapshot, Path::new(rel), true).unwrap();
+        assert_eq!(read("real/a").unwrap().kind, "file");
+        // 文件本身是符号链接: 不跟随
+        assert_eq!(read("real/b").unwrap().kind, "symlink");
+        assert_eq!(read("real/x"), None);
+        assert_eq!(read("link/a"), None);
+        assert_eq!(read("real/a/x"), None);
+        assert_eq!(read("real/../real/a"), None);
+    }
+}
@@ -0,0 +1,60 @@
+//! pmbs fsck: 检查 (并修复) 快照保存目录
+use log::info;
+
+use crate::{
+    config::{find_config, get_env_config},
+    error::{PmbsError, PmbsResult},
+    snapshot::{PmbsLock, SafeDir, fsck_check, fsck_repair},
+};
+
+use super::help::bad_cli_arg;
+
+/// pmbs fsck SUBVOL [--repair]
+pub fn c_fsck(a: Vec<String>) -> PmbsResult<()> {
+    // 解析命令行参数
+    let repair = match a.len() {
+        1 => false,
+        2 if a[1] == "--repair" => true,
+        _ => {
+            return Err(bad_cli_arg());
+        }
+    };
+    let c = get_env_config();
+    let config = find_config(&c, &a[0], !repair)?;
+    let dir = config.dir_pmbs();
+
+    // 修复时需要获取锁
+    let list = if repair {
+        let pmbs = SafeDir::open_pmbs(&dir, false)?;
+        let _lock = PmbsLock::new(&pmbs, config.lock_timeout_s)?;
+        fsck_repair(&c, &pmbs)?
+    } else {
+        fsck_check(&c, &dir)?
+            .into_iter()
+            .map(|x| (x, false))
+            .collect()
+    };
+
+    let mut bad = 0;
+    for (i, fixed) in &list {
+        let mark = if *fixed {
+            "  *repaired"
+        } else if repair || !i.can_repair() {
+            bad += 1;
+            "  *no repair"
+        } else {
+            bad += 1;
+            ""
+        };
+        println!("{}{}", i.describe(), mark);
+    }
+    if bad > 0 {
+        return Err(PmbsError::Problem(format!(
+            "fsck  {} problems  {}",
+            bad,
+            dir.to_string_lossy()
+        )));
+    }
+    info!("fsck ok  {}", dir.to_string_lossy());
+    Ok(())
+}
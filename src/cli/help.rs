@@ -1,17 +1,45 @@
 //! 输出命令行帮助信息
+use crate::error::PmbsError;
 
 /// --help
 pub fn help_en() {
     println!(
         r#"pmbs: Make btrfs snapshot (every minute), and auto clean.
-Usage: pmbs COMMAND ARG..
+Usage: pmbs [--json] COMMAND ARG..
 
-pmbs snapshot SUBVOL
+--json (or env PMBS_OUTPUT=json): print a versioned JSON document instead of text,
+    for `ls`, `find`, `log`, `config test` and `config test-clean`.
+
+pmbs snapshot SUBVOL [--label TEXT]
     Create a snapshot of the btrfs SUBVOL (path).
+    The label (note) TEXT is saved with the snapshot, and shown by `pmbs ls`.
 
 pmbs ls SUBVOL
     List all snapshots of the SUBVOL (path).
 
+pmbs pin SUBVOL SNAPSHOT [--until TIME]
+    Pin the SNAPSHOT (like 2025/1756392923), so auto clean never deletes it.
+    TIME: time length (like 7d), timestamp, or RFC 3339 (like 2025-09-01T00:00:00+08:00).
+
+pmbs unpin SUBVOL SNAPSHOT
+    Unpin the SNAPSHOT.
+
+pmbs find PATH [--hash]
+    List every version of the file (or dir) PATH in all snapshots. Snapshots with the same
+    type, size and mtime (and content hash, with --hash) are collapsed into one version,
+    shown with the time range (first ~ last snapshot), and `*current` if same as now.
+    Works as a normal user (snapshots are readable).
+
+pmbs log [--config NAME] [--since TIME] [--json] [SNAPSHOT]
+    Read the clean logs, list deleted snapshots (time, config, reason, result),
+    and count per day. With SNAPSHOT (like 2025/1756392923): when and why it was deleted.
+    TIME: time length (like 7d, means 7 days ago), timestamp, or RFC 3339.
+
+pmbs fsck SUBVOL [--repair]
+    Check the snapshot dir (like /home/.pmbs): latest symlink (missing, dangling, not newest),
+    leftover latest.T temp symlinks, snapshots in the wrong year dir, entries that are not
+    (read-only) subvols, and empty year dirs. With --repair, fix what can be fixed safely.
+
 ----
 Batch command:
 
@@ -36,6 +64,24 @@ pmbs config test
 pmbs config test-clean PATH
     Read the config file, and test clean snapshots (not execute clean actually).
 
+pmbs config explain PATH [SNAPSHOT]
+    Read the config file, and show why each snapshot (or only SNAPSHOT) is kept or cleaned:
+    the rule and slot that keeps it, keep_latest, pinned, masked by an interval,
+    or rules exhausted. Global limits (max_age, max_total, min_keep) are marked with `*`.
+
+pmbs config simulate PATH [--days 30] [--interval 1m] [--jitter 2s] [--gaps 3d+12h,...]
+    Read the config file, make snapshots on a synthetic timeline, and run auto clean
+    every 10 minutes (--clean-every). Report the kept snapshots: count per age band,
+    max gap, and count at the end of each day.
+    --gaps START+LEN: no snapshot in this time range (e.g. computer is off),
+    START is relative to the start of the simulation.
+
+----
+Maintenance command:
+
+pmbs config migrate PATH
+    Read the config file, and move existing snapshots from SUBVOL/.pmbs to snapshot_dir.
+
 ----
 pmbs --version
     Show version info.
@@ -57,14 +103,41 @@ More info: <https://github.com/fm-elpac/pmbs> <https://crates.io/crates/pmbs>"#
 pub fn help_zh() {
     println!(
         r#"胖喵必快 (pmbs): (每分钟) 创建 btrfs 快照, 并自动清理.
-用法: pmbs 命令 参数..
+用法: pmbs [--json] 命令 参数..
 
-pmbs snapshot SUBVOL
+--json (或者环境变量 PMBS_OUTPUT=json): 输出 (有版本的) JSON 文档, 而不是文本,
+    用于 `ls`, `find`, `log`, `config test` 和 `config test-clean`.
+
+pmbs snapshot SUBVOL [--label TEXT]
     创建指定 btrfs subvol 的快照.
+    标签 (备注) TEXT 和快照一起保存, 使用 `pmbs ls` 显示.
 
 pmbs ls SUBVOL
     列出对应 subvol 的所有快照.
 
+pmbs pin SUBVOL SNAPSHOT [--until TIME]
+    固定指定快照 (比如 2025/1756392923), 自动清理永远不会删除.
+    TIME: 时间长度 (比如 7d), 时间戳, 或者 RFC 3339 格式 (比如 2025-09-01T00:00:00+08:00).
+
+pmbs unpin SUBVOL SNAPSHOT
+    取消固定指定快照.
+
+pmbs find PATH [--hash]
+    列出文件 (或目录) PATH 在所有快照中的每个版本. 类型, 大小, 修改时间
+    (指定 --hash 时还有内容的 hash) 相同的连续快照合并为一个版本,
+    显示时间范围 (最早 ~ 最晚的快照), 和现在的文件相同时显示 `*current`.
+    普通用户也可以使用 (快照是可读的).
+
+pmbs log [--config NAME] [--since TIME] [--json] [SNAPSHOT]
+    读取清理日志, 列出删除的快照 (时间, 配置文件, 原因, 结果), 以及每天的统计.
+    指定 SNAPSHOT (比如 2025/1756392923): 查询这个快照什么时候, 为什么被删除.
+    TIME: 时间长度 (比如 7d, 表示 7 天之前), 时间戳, 或者 RFC 3339 格式.
+
+pmbs fsck SUBVOL [--repair]
+    检查快照保存目录 (比如 /home/.pmbs): latest 符号链接 (不存在, 指向不存在的快照, 不是最新),
+    残留的 latest.T 临时符号链接, 年目录错误的快照, 不是 (只读) subvol 的目录, 空的年目录.
+    指定 --repair: 修复可以安全修复的问题.
+
 ----
 批量执行命令:
 
@@ -89,6 +162,22 @@ pmbs config test
 pmbs config test-clean PATH
     读取指定配置文件, 测试清理快照 (并不实际执行).
 
+pmbs config explain PATH [SNAPSHOT]
+    读取指定配置文件, 显示每个快照 (或者只显示 SNAPSHOT) 保留/清理 的原因:
+    保留它的规则和第几个, keep_latest, 固定的快照, 被间隔遮盖, 或者规则已用完.
+    全局限制 (max_age, max_total, min_keep) 使用 `*` 标记.
+
+pmbs config simulate PATH [--days 30] [--interval 1m] [--jitter 2s] [--gaps 3d+12h,...]
+    读取指定配置文件, 在模拟的时间线上创建快照, 并每隔 10 分钟 (--clean-every)
+    执行自动清理. 输出最后保留的快照: 每个年龄分段的个数, 最大间隔, 每天结束时的个数.
+    --gaps START+LEN: 这段时间不创建快照 (比如关机), START 相对于模拟开始的时间.
+
+----
+维护命令:
+
+pmbs config migrate PATH
+    读取指定配置文件, 把现有快照从 SUBVOL/.pmbs 移动到 snapshot_dir.
+
 ----
 pmbs --版本
     显示版本信息.
@@ -106,7 +195,7 @@ pmbs --help
     );
 }
 
-/// 命令行参数错误信息
-pub fn bad_cli_arg() {
-    eprintln!("ERROR: Bad command arg, try --help");
+/// 命令行参数错误
+pub fn bad_cli_arg() -> PmbsError {
+    PmbsError::Arg("Bad command arg, try --help".into())
 }
@@ -0,0 +1,378 @@
+//! pmbs log: 查询清理日志 (清理历史)
+use std::{
+    fmt::Display,
+    fs::{read_dir, read_to_string},
+    io,
+    path::PathBuf,
+};
+
+use chrono::{Local, TimeZone};
+use log::{debug, info, warn};
+use serde::{Deserialize, Deserializer, Serialize};
+
+use crate::{
+    clean::RmStatus,
+    config::{ConfigEnv, get_env_config, parse_time},
+    error::{PmbsError, PmbsResult},
+    snapshot::{format_t_local, get_t, parse_snapshot_t},
+};
+
+use super::{
+    CleanItem, CleanLog, CleanReason,
+    help::bad_cli_arg,
+    output::{is_json, print_json},
+    pin::parse_until,
+};
+
+/// 兼容旧版本的清理日志: `clean` 只是快照路径列表
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum CleanItemCompat {
+    Item(CleanItem),
+    Path(String),
+}
+
+/// 读取清理日志中的 `clean` (兼容旧版本)
+pub fn de_clean_list<'de, D: Deserializer<'de>>(d: D) -> Result<Vec<CleanItem>, D::Error> {
+    let list = Vec::<CleanItemCompat>::deserialize(d)?;
+    Ok(list
+        .into_iter()
+        .map(|x| match x {
+            CleanItemCompat::Item(i) => i,
+            // 旧版本只有保留规则
+            CleanItemCompat::Path(path) => CleanItem {
+                t: 0,
+                path,
+                reason: CleanReason::Rule { why: None },
+                result: None,
+            },
+        })
+        .collect())
+}
+
+/// 读取全部清理日志 `clean-*.log.json` (按清理时间升序)
+///
+/// 无法解析的日志文件输出警告, 然后忽略.
+fn read_clean_log(c: &ConfigEnv) -> PmbsResult<Vec<CleanLog>> {
+    let dir = PathBuf::from(&c.dir_log);
+    let rd = match read_dir(&dir) {
+        Ok(rd) => rd,
+        // 日志目录不存在: 还没有清理过
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {
+            return Ok(Vec::new());
+        }
+        Err(e) => {
+            return Err(PmbsError::Io(dir.to_string_lossy().to_string(), e));
+        }
+    };
+    let mut o = Vec::new();
+    for i in rd {
+        let p = i.map_err(PmbsError::io(&dir))?.path();
+        let name = p.file_name().unwrap_or_default().to_string_lossy();
+        if !(name.starts_with("clean-") && name.ends_with(".log.json")) {
+            continue;
+        }
+        debug!("read log {}", p.to_string_lossy());
+        let text = read_to_string(&p).map_err(PmbsError::io(&p))?;
+        match serde_json::from_str::<CleanLog>(&text) {
+            Ok(mut log) => {
+                for i in log.clean.iter_mut().chain(log.deferred.iter_mut()) {
+                    i.t = parse_snapshot_t(&i.path).unwrap_or(0);
+                }
+                o.push(log);
+            }
+            Err(e) => warn!("bad log, skip  {}  {}", p.to_string_lossy(), e),
+        }
+    }
+    o.sort_by_key(|x| x.t);
+    Ok(o)
+}
+
+/// 查询条件
+#[derive(Debug, Clone, Default)]
+struct LogQuery {
+    /// 配置文件名, 比如 `home.toml` 或者 `home`
+    config: Option<String>,
+    /// 只查询这个时间 (时间戳) 之后的清理
+    since: Option<u64>,
+    /// 只查询这个快照 (时间戳)
+    snapshot: Option<u64>,
+}
+
+/// 一个快照的清理记录
+#[derive(Debug, Clone, Serialize)]
+struct LogItem {
+    /// 清理时间
+    t: u64,
+    /// 配置文件名
+    config: String,
+    /// 清理的快照, 清理原因, 删除结果
+    #[serde(flatten)]
+    item: CleanItem,
+}
+
+/// 每天的清理统计 (本地时间)
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+struct LogDay {
+    /// 日期, 比如 `2025-08-28`
+    day: String,
+    /// 清理的快照个数
+    clean: usize,
+    /// 已删除
+    deleted: usize,
+    /// 删除失败
+    failed: usize,
+    /// 快照已经不存在, 跳过
+    gone: usize,
+}
+
+/// pmbs log --json 的输出
+#[derive(Debug, Clone, Serialize)]
+struct LogReport {
+    /// 清理记录 (按清理时间升序)
+    item: Vec<LogItem>,
+    /// 每天的统计
+    daily: Vec<LogDay>,
+}
+
+/// 按照查询条件, 列出清理的快照
+fn query(list: Vec<CleanLog>, q: &LogQuery) -> Vec<LogItem> {
+    let mut o = Vec::new();
+    for log in list {
+        if let Some(name) = &q.config
+            && log.config.path != *name
+            && log.config.path.strip_suffix(".toml") != Some(name.as_str())
+        {
+            continue;
+        }
+        if let Some(since) = q.since
+            && log.t < since
+        {
+            continue;
+        }
+        for item in log.clean {
+            if let Some(t) = q.snapshot
+                && item.t != t
+            {
+                continue;
+            }
+            o.push(LogItem {
+                t: log.t,
+                config: log.config.path.clone(),
+                item,
+            });
+        }
+    }
+    o
+}
+
+/// 按天统计 (`tz`: 时区, 通常是 `Local`)
+fn daily<Tz: TimeZone>(list: &[LogItem], tz: &Tz) -> Vec<LogDay>
+where
+    Tz::Offset: Display,
+{
+    let mut o: Vec<LogDay> = Vec::new();
+    for i in list {
+        let day = match tz.timestamp_opt(i.t as i64, 0).single() {
+            Some(t) => t.format("%Y-%m-%d").to_string(),
+            None => continue,
+        };
+        if o.last().map(|x| &x.day) != Some(&day) {
+            o.push(LogDay {
+                day,
+                ..Default::default()
+            });
+        }
+        // 上面刚刚添加, 一定存在
+        let d = o.last_mut().unwrap();
+        d.clean += 1;
+        match i.item.result.as_ref().map(|x| x.status) {
+            Some(RmStatus::Deleted) => d.deleted += 1,
+            Some(RmStatus::Failed) => d.failed += 1,
+            Some(RmStatus::Gone) => d.gone += 1,
+            None => {}
+        }
+    }
+    o
+}
+
+/// 清理原因 (显示)
+fn reason_str(r: &CleanReason) -> String {
+    match r {
+        CleanReason::Rule { why: None } => "rule".into(),
+        // 被遮盖, 规则用尽: 不需要显示规则
+        CleanReason::Rule { why: Some(r) } => format!("rule ({})", r.describe(&[])),
+        CleanReason::MaxAge => "max_age".into(),
+        CleanReason::MaxTotal => "max_total".into(),
+        CleanReason::SpaceBudget { tier, .. } => format!("space_budget ({})", tier),
+    }
+}
+
+/// pmbs log [--config NAME] [--since TIME] [--json] [SNAPSHOT]
+pub fn c_log(a: Vec<String>) -> PmbsResult<()> {
+    // 解析命令行参数
+    let now = get_t();
+    let c = get_env_config();
+    let mut q = LogQuery::default();
+    let mut json = is_json(&c);
+    let mut i = a.iter();
+    while let Some(x) = i.next() {
+        match x.as_str() {
+            "--json" => json = true,
+            "--config" => q.config = Some(i.next().ok_or_else(bad_cli_arg)?.clone()),
+            "--since" => {
+                let v = i.next().ok_or_else(bad_cli_arg)?;
+                // 时间长度表示多久之前
+                let t = match parse_time(v) {
+                    Some(d) => Some(now.saturating_sub(d)),
+                    None => parse_until(v, now),
+                };
+                q.since = Some(t.ok_or_else(|| PmbsError::Arg(format!("bad time  {}", v)))?);
+            }
+            _ if !x.starts_with("--") && q.snapshot.is_none() => {
+                q.snapshot = Some(
+                    parse_snapshot_t(x)
+                        .ok_or_else(|| PmbsError::Arg(format!("bad snapshot  {}", x)))?,
+                );
+            }
+            _ => {
+                return Err(bad_cli_arg());
+            }
+        }
+    }
+
+    let list = query(read_clean_log(&c)?, &q);
+    let day = daily(&list, &Local);
+    if json {
+        let r = LogReport {
+            item: list,
+            daily: day,
+        };
+        print_json("log", r);
+        return Ok(());
+    }
+
+    if list.is_empty()
+        && let Some(t) = q.snapshot
+    {
+        info!("snapshot not found in clean log  {}", t);
+        return Ok(());
+    }
+    for i in &list {
+        let status = match &i.item.result {
+            Some(r) => r.status.as_str(),
+            None => "-",
+        };
+        println!(
+            "{}\t{}\t{}\t{}\t{}",
+            format_t_local(i.t),
+            i.config,
+            i.item.path,
+            reason_str(&i.item.reason),
+            status
+        );
+    }
+    for d in &day {
+        println!(
+            "day {}  clean {}  deleted {}  failed {}  gone {}",
+            d.day, d.clean, d.deleted, d.failed, d.gone
+        );
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use chrono::{FixedOffset, Utc};
+
+    use super::*;
+
+    fn read(text: &str) -> CleanLog {
+        let mut log: CleanLog = serde_json::from_str(text).unwrap();
+        for i in log.clean.iter_mut() {
+            i.t = parse_snapshot_t(&i.path).unwrap();
+        }
+        log
+    }
+
+    #[test]
+    fn query_log() {
+        let config = r#""config": {"path": "home.toml", "config": {"pmbs": 1, "subvol": "/home", "keep": []}}"#;
+        // 旧版本的清理日志
+        let old = read(&format!(
+            r#"{{"t": 1756392900, {}, "keep": [], "clean": ["2025/1756300000"]}}"#,
+            config
+        ));
+        assert_eq!(old.clean[0].t, 1756300000);
+        assert!(matches!(
+            old.clean[0].reason,
+            CleanReason::Rule { why: None }
+        ));
+
+        let new = read(&format!(
+            r#"{{"t": 1756392960, {}, "keep": [], "clean": [
+                {{"path": "2025/1756310000", "reason": "max_age", "result": {{"status": "deleted"}}}},
+                {{"path": "2025/1756320000", "reason": "space_budget", "tier": "1h ~ 1d", "free": 1,
+                  "result": {{"status": "failed", "code": 1, "error": "command failed"}}}}
+            ], "result": {{"deleted": 1, "failed": 1, "gone": 0}}}}"#,
+            config
+        ));
+        // 第二天 (UTC+8) 的清理
+        let next = read(&format!(
+            r#"{{"t": 1756396800, {}, "keep": [], "clean": [
+                {{"path": "2025/1756330000", "reason": "max_total", "result": {{"status": "gone"}}}}
+            ], "result": {{"deleted": 0, "failed": 0, "gone": 1}}}}"#,
+            config
+        ));
+        let list = vec![old, new, next];
+
+        let all = query(list.clone(), &LogQuery::default());
+        assert_eq!(all.len(), 4);
+        // 2025-08-28 22:55, 22:56 和 2025-08-29 00:00 (UTC+8)
+        let tz = FixedOffset::east_opt(8 * 3600).unwrap();
+        assert_eq!(
+            daily(&all, &tz),
+            vec![
+                LogDay {
+                    day: "2025-08-28".into(),
+                    clean: 3,
+                    deleted: 1,
+                    failed: 1,
+                    gone: 0,
+                },
+                LogDay {
+                    day: "2025-08-29".into(),
+                    clean: 1,
+                    deleted: 0,
+                    failed: 0,
+                    gone: 1,
+                },
+            ]
+        );
+        // UTC: 同一天
+        assert_eq!(daily(&all, &Utc).len(), 1);
+        assert_eq!(daily(&all, &Utc)[0].day, "2025-08-28");
+        assert_eq!(reason_str(&all[2].item.reason), "space_budget (1h ~ 1d)");
+
+        // 快照什么时候, 为什么被删除
+        let q = LogQuery {
+            snapshot: parse_snapshot_t("2025/1756310000"),
+            ..Default::default()
+        };
+        let r = query(list.clone(), &q);
+        assert_eq!((r.len(), r[0].t), (1, 1756392960));
+        assert!(matches!(r[0].item.reason, CleanReason::MaxAge));
+
+        let q = LogQuery {
+            config: Some("home".into()),
+            since: Some(1756392950),
+            ..Default::default()
+        };
+        assert_eq!(query(list.clone(), &q).len(), 3);
+        let q = LogQuery {
+            config: Some("root.toml".into()),
+            ..Default::default()
+        };
+        assert!(query(list, &q).is_empty());
+    }
+}
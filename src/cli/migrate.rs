@@ -0,0 +1,228 @@
+//! pmbs config migrate: 把现有快照移动到 snapshot_dir
+use std::{
+    fs::{read_dir, read_link, remove_dir},
+    io,
+    path::{Path, PathBuf},
+};
+
+use log::{debug, error, info, warn};
+
+use crate::{
+    clean::{FILE_PIN, ls_snapshot},
+    config::{PmbsConfig, read_config, same_mount},
+    error::{PmbsError, PmbsResult},
+    snapshot::{DIR_META, DIR_PMBS, FILE_LOCK, PmbsLock, SYMLINK_LATEST, SafeDir, get_t},
+};
+
+use super::help::bad_cli_arg;
+
+/// 移动文件 (可能跨 subvol, 所以先复制再删除), 不跟随符号链接
+fn move_file(from: &SafeDir, to: &SafeDir, name: &str) -> PmbsResult<()> {
+    debug!(
+        "move {} -> {}",
+        from.path.join(name).to_string_lossy(),
+        to.path.join(name).to_string_lossy()
+    );
+    if let Some(data) = from.read_file(name)? {
+        to.write_file(name, &data)?;
+        from.remove_file(name)?;
+    }
+    Ok(())
+}
+
+/// 移动一个快照 `YEAR/T` (btrfs 允许跨 subvol 移动 subvol)
+fn move_snapshot(from: &SafeDir, to: &SafeDir, year: i32, t: u64) -> PmbsResult<()> {
+    let year = format!("{}", year);
+    let t = format!("{}", t);
+    open_dir(from, &year, false)?.rename(&t, &open_dir(to, &year, true)?, &t)
+}
+
+/// 删除空目录 (忽略错误)
+fn rm_empty_dir(p: &Path) {
+    if remove_dir(p).is_ok() {
+        debug!("rm dir {}", p.to_string_lossy());
+    }
+}
+
+/// 打开 (创建) 子目录, 不存在时返回错误
+fn open_dir(d: &SafeDir, name: &str, create: bool) -> PmbsResult<SafeDir> {
+    d.open_dir(name, create)?
+        .ok_or_else(|| PmbsError::io(&d.path.join(name))(io::ErrorKind::NotFound.into()))
+}
+
+/// pmbs config migrate PATH
+pub fn c_config_migrate(a: Vec<String>) -> PmbsResult<()> {
+    // 解析命令行参数
+    if a.len() != 1 {
+        return Err(bad_cli_arg());
+    }
+    let config = read_config(&PathBuf::from(&a[0]))?.config;
+    migrate(&config)
+}
+
+/// 把 `subvol/.pmbs` 中的快照, 元数据, 固定快照列表, latest 移动到 snapshot_dir
+fn migrate(config: &PmbsConfig) -> PmbsResult<()> {
+    // 原来的快照保存目录: subvol/.pmbs
+    let from = PathBuf::from(&config.subvol).join(DIR_PMBS);
+    let to = config.dir_pmbs();
+    if from == to {
+        info!("nothing to migrate  {}", from.to_string_lossy());
+        return Ok(());
+    }
+    // 打开原目录和目标目录 (不跟随符号链接, 检查所有者)
+    let from_dir = match SafeDir::try_open_pmbs(&from)? {
+        Some(d) => d,
+        None => {
+            info!("dir not exist  {}", from.to_string_lossy());
+            return Ok(());
+        }
+    };
+    // rename 不能跨挂载点 (EXDEV), 即使是同一个 btrfs 文件系统的不同 subvol
+    if same_mount(&from, &to) == Some(false) {
+        return Err(PmbsError::Safety(format!(
+            "snapshot_dir not on the same mount as subvol, can not move snapshots  {}",
+            to.to_string_lossy()
+        )));
+    }
+    let to_dir = SafeDir::open_pmbs(&to, true)?;
+
+    // 获取锁 (原目录和目标目录)
+    let lock = (
+        PmbsLock::new(&from_dir, config.lock_timeout_s)?,
+        PmbsLock::new(&to_dir, config.lock_timeout_s)?,
+    );
+
+    // 按时间顺序移动
+    let mut list = ls_snapshot(&from)?;
+    list.sort_by_key(|x| x.t);
+    // 检查: 目标目录不能已经有相同的快照
+    for i in &list {
+        let p = to.join(&i.path);
+        if p.symlink_metadata().is_ok() {
+            return Err(PmbsError::Safety(format!(
+                "snapshot already exist  {}",
+                p.to_string_lossy()
+            )));
+        }
+    }
+
+    // 移动快照
+    for (n, i) in list.iter().enumerate() {
+        let p = to.join(&i.path);
+        info!("move {} -> {}", i.p.to_string_lossy(), p.to_string_lossy());
+        if let Err(e) = move_snapshot(&from_dir, &to_dir, i.year, i.t) {
+            // 移动失败: 把已经移动的快照移回原目录, 避免快照分散在两个目录中
+            for j in list[..n].iter().rev() {
+                info!("move back {}", j.p.to_string_lossy());
+                if let Err(e) = move_snapshot(&to_dir, &from_dir, j.year, j.t) {
+                    error!("move back failed  {}  {}", j.p.to_string_lossy(), e);
+                }
+            }
+            return Err(e);
+        }
+    }
+
+    // 移动元数据
+    if let Some(meta) = from_dir.open_dir(DIR_META, false)? {
+        let d = open_dir(&to_dir, DIR_META, true)?;
+        for i in read_dir(&meta.path).map_err(PmbsError::io(&meta.path))? {
+            let f = i.map_err(PmbsError::io(&meta.path))?;
+            move_file(&meta, &d, &f.file_name().to_string_lossy())?;
+        }
+        rm_empty_dir(&meta.path);
+    }
+    // 移动固定快照列表
+    if from.join(FILE_PIN).symlink_metadata().is_ok() {
+        if to.join(FILE_PIN).symlink_metadata().is_ok() {
+            warn!(
+                "pin list already exist, skip  {}",
+                from.join(FILE_PIN).to_string_lossy()
+            );
+        } else {
+            move_file(&from_dir, &to_dir, FILE_PIN)?;
+        }
+    }
+
+    // 重新创建 latest 符号链接
+    let latest = from.join(SYMLINK_LATEST);
+    if let Ok(link_to) = read_link(&latest) {
+        info!(
+            "symlink {} -> {}",
+            to.join(SYMLINK_LATEST).to_string_lossy(),
+            link_to.to_string_lossy()
+        );
+        let tmp = format!("{}.{}", SYMLINK_LATEST, get_t());
+        to_dir.write_symlink(&link_to, SYMLINK_LATEST, &tmp)?;
+        from_dir.remove_file(SYMLINK_LATEST)?;
+    }
+
+    // 删除空目录
+    drop(lock);
+    let _ = from_dir.remove_file(FILE_LOCK);
+    for i in &list {
+        rm_empty_dir(&from.join(format!("{}", i.year)));
+    }
+    rm_empty_dir(&from);
+    if from.exists() {
+        warn!("dir not empty  {}", from.to_string_lossy());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        fs::{create_dir_all, read_to_string, remove_file, write},
+        os::unix::fs::symlink,
+    };
+
+    use super::*;
+    use crate::test_util::TmpDir;
+
+    #[test]
+    fn migrate_dir() {
+        let tmp = TmpDir::new("migrate");
+        let subvol = tmp.join("home");
+        let from = subvol.join(DIR_PMBS);
+        for t in [1756392923, 1756392983] {
+            create_dir_all(from.join(format!("2025/{}", t))).unwrap();
+        }
+        create_dir_all(from.join(DIR_META)).unwrap();
+        write(from.join("meta/1756392983.json"), "{}").unwrap();
+        write(from.join(FILE_PIN), r#"{"pin": []}"#).unwrap();
+        symlink("2025/1756392983", from.join(SYMLINK_LATEST)).unwrap();
+
+        let mut config = PmbsConfig::new_subvol(&subvol.to_string_lossy());
+        let to = tmp.join(".pmbs-store/home");
+        config.snapshot_dir = Some(to.to_string_lossy().to_string());
+        // 目标目录已经有相同的快照: 不移动
+        create_dir_all(to.join("2025/1756392923")).unwrap();
+        assert!(matches!(migrate(&config), Err(PmbsError::Safety(_))));
+        assert_eq!(ls_snapshot(&from).unwrap().len(), 2);
+        remove_dir(to.join("2025/1756392923")).unwrap();
+
+        // 移动中途失败 (目标目录中 2026 不是目录): 已经移动的快照移回原目录
+        create_dir_all(from.join("2026/1767225600")).unwrap();
+        write(to.join("2026"), "").unwrap();
+        assert!(migrate(&config).is_err());
+        assert_eq!(ls_snapshot(&from).unwrap().len(), 3);
+        assert!(ls_snapshot(&to).unwrap().is_empty());
+        remove_file(to.join("2026")).unwrap();
+
+        migrate(&config).unwrap();
+        assert!(!from.exists());
+        let list = ls_snapshot(&to).unwrap();
+        assert_eq!(list.len(), 3);
+        let latest: Vec<u64> = list.iter().filter(|x| x.latest).map(|x| x.t).collect();
+        assert_eq!(latest, vec![1756392983]);
+        assert_eq!(
+            read_to_string(to.join("meta/1756392983.json")).unwrap(),
+            "{}"
+        );
+        assert!(to.join(FILE_PIN).is_file());
+
+        // 再次执行: 没有需要移动的
+        migrate(&config).unwrap();
+        assert_eq!(ls_snapshot(&to).unwrap().len(), 3);
+    }
+}
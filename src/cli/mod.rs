@@ -1,102 +1,170 @@
 //! 命令行相关处理
 use std::{
+    cmp::Reverse,
     env,
     fs::{create_dir_all, write},
     path::PathBuf,
     process::{Command, ExitCode},
 };
 
-use log::{debug, info};
-use serde::Serialize;
+use log::{debug, error, info};
+use serde::{Deserialize, Serialize};
 
+mod explain;
+mod find;
+mod fsck;
 mod help;
+mod history;
+mod migrate;
+mod output;
+mod pin;
 mod sh;
+mod simulate;
+mod space;
 
-pub use sh::sh_run;
+pub use sh::{sh_output, sh_run, sh_run_timeout};
 
 use crate::{
-    clean::{Snapshot, decide, ls_snapshot, safe_rm_subvol_list},
-    config::{ConfigEnv, PmbsConfigFile, get_env_config, list_config, read_config},
-    snapshot::{format_t_local, get_t, make_snapshot},
+    clean::{
+        Decision, Force, Reason, RmOpt, RmResult, RmStatus, decide, decide_calendar, ls_snapshot,
+        safe_rm_subvol_list,
+    },
+    config::{
+        ConfigEnv, PmbsConfig, PmbsConfigFile, find_config, get_env_config, list_config,
+        read_config,
+    },
+    error::{PmbsError, PmbsResult},
+    snapshot::{
+        PmbsLock, SafeDir, SnapshotOpt, Trigger, check_interval, format_t_local, get_t,
+        make_snapshot,
+    },
 };
 
+use explain::c_config_explain;
+use find::c_find;
+use fsck::c_fsck;
 use help::bad_cli_arg;
+use history::{c_log, de_clean_list};
+use migrate::c_config_migrate;
+use output::{
+    ConfigTestItem, ConfigTestOutput, DecisionItem, LsOutput, TestCleanOutput, is_json, print_json,
+    set_json,
+};
+use pin::{c_pin, c_unpin};
+use simulate::c_config_simulate;
+use space::{check_space, clean_space_budget};
 
-/// pmbs snapshot SUBVOL
-fn c_snapshot(a: Vec<String>) -> Result<(), ExitCode> {
+/// pmbs snapshot SUBVOL [--label TEXT]
+fn c_snapshot(a: Vec<String>) -> PmbsResult<()> {
     // 解析命令行参数
-    if a.len() != 1 {
-        bad_cli_arg();
-        return Err(ExitCode::from(1));
-    }
+    let label = match a.len() {
+        1 => None,
+        3 if a[1] == "--label" => Some(a[2].clone()),
+        _ => {
+            return Err(bad_cli_arg());
+        }
+    };
     let subvol = &a[0];
 
     // 读取环境配置
     let c = get_env_config();
+    // 如果有这个 subvol 的配置文件, 使用其中的快照保存目录
+    let mut config = PmbsConfig::new_subvol(subvol);
+    let found = find_config(&c, subvol, false)?;
+    config.snapshot_dir = found.snapshot_dir;
+    config.lock_timeout_s = found.lock_timeout_s;
     // 创建快照
-    make_snapshot(&c, subvol)
+    let opt = SnapshotOpt {
+        trigger: Trigger::from_env(Trigger::Manual),
+        label,
+        ..Default::default()
+    };
+    make_snapshot(&c, &config, &opt)
 }
 
 /// pmbs ls SUBVOL
-fn c_ls(a: Vec<String>) -> Result<(), ExitCode> {
+fn c_ls(a: Vec<String>) -> PmbsResult<()> {
     // 解析命令行参数
     if a.len() != 1 {
-        bad_cli_arg();
-        return Err(ExitCode::from(1));
+        return Err(bad_cli_arg());
     }
     let subvol = &a[0];
 
     // 获取所有快照
-    let mut list = ls_snapshot(subvol);
+    let c = get_env_config();
+    let dir = find_config(&c, subvol, true)?.dir_pmbs();
+    let mut list = ls_snapshot(&dir)?;
     // 排序 (按时间降序, 最新的在最前面)
-    list.sort_by(|a, b| b.t.cmp(&a.t));
+    list.sort_by_key(|x| Reverse(x.t));
+    if is_json(&c) {
+        let o = LsOutput {
+            subvol,
+            dir: dir.to_string_lossy().to_string(),
+            snapshot: &list,
+        };
+        print_json("ls", o);
+        return Ok(());
+    }
 
     // 输出
     for i in list {
         let latest = if i.latest { "\t*latest" } else { "" };
-
+        let pinned = if i.pinned { "\t*pinned" } else { "" };
+        let (trigger, config, label) = match &i.meta {
+            Some(m) => (
+                m.trigger.as_str(),
+                m.config.as_deref().unwrap_or("-"),
+                m.label.as_deref().unwrap_or("-"),
+            ),
+            None => ("-", "-", "-"),
+        };
+
+        // 新增的列在原有的列之后
         println!(
-            "{}\t{}{}",
+            "{}\t{}{}{}\t{}\t{}\t{}",
             i.p.to_string_lossy(),
             format_t_local(i.t),
-            latest
+            latest,
+            pinned,
+            trigger,
+            config,
+            label
         );
     }
     Ok(())
 }
 
 /// 获取自己的可执行文件路径
-fn get_exe() -> PathBuf {
-    env::current_exe().unwrap()
+fn get_exe() -> PmbsResult<PathBuf> {
+    env::current_exe().map_err(PmbsError::io("/proc/self/exe"))
 }
 
 /// 读取所有配置文件, 调用自己, 使用 subprocess 执行每个配置文件
-fn run_config(c: &ConfigEnv, a: &str) -> Result<(), ExitCode> {
+fn run_config(c: &ConfigEnv, a: &str) -> PmbsResult<()> {
     let mut r = Ok(());
 
-    for i in list_config(c) {
+    for i in list_config(c)? {
         // 调用自己, 在 subprocess 中实际执行
-        let mut c = Command::new(get_exe());
+        let mut c = Command::new(get_exe()?);
         c.arg("config").arg(a).arg(i);
 
-        let code = sh_run(c);
         // 忽略错误, 稍后返回错误
-        if 0 != code {
-            r = Err(ExitCode::from(1));
+        if let Err(e) = sh_run(c) {
+            error!("{}", e);
+            r = Err(e);
         }
     }
     r
 }
 
 /// pmbs config snapshot {PATH}
-fn c_config_snapshot(a: Vec<String>) -> Result<(), ExitCode> {
+fn c_config_snapshot(a: Vec<String>) -> PmbsResult<()> {
     // 解析命令行参数
     let path = match a.len() {
         0 => None,
         1 => Some(&a[0]),
         _ => {
-            bad_cli_arg();
-            return Err(ExitCode::from(1));
+            return Err(bad_cli_arg());
         }
     };
     // 读取环境配置
@@ -104,30 +172,39 @@ fn c_config_snapshot(a: Vec<String>) -> Result<(), ExitCode> {
 
     match path {
         // 执行指定配置文件
-        Some(path) => match read_config(&PathBuf::from(path)) {
-            Some(config) => {
-                debug!("config  {}", serde_json::to_string(&config).unwrap());
-                // 创建快照
-                make_snapshot(&c, &config.config.subvol)
+        Some(path) => {
+            let config = read_config(&PathBuf::from(path))?;
+            debug!("config  {}", serde_json::to_string(&config).unwrap());
+            // 检查间隔时间 (获取锁之后会再次检查)
+            if check_interval(&config.config, get_t()) {
+                return Ok(());
             }
-            None => {
-                return Err(ExitCode::from(1));
+            // 检查剩余存储空间
+            if !check_space(&c, path, &config) {
+                return Ok(());
             }
-        },
+            // 创建快照
+            let opt = SnapshotOpt {
+                config: Some(config.path.clone()),
+                trigger: Trigger::from_env(Trigger::Timer),
+                interval: true,
+                ..Default::default()
+            };
+            make_snapshot(&c, &config.config, &opt)
+        }
         // 读取所有配置文件 (执行 pmbs config snapshot)
         None => run_config(&c, "snapshot"),
     }
 }
 
 /// pmbs config clean {PATH}
-fn c_config_clean(a: Vec<String>) -> Result<(), ExitCode> {
+fn c_config_clean(a: Vec<String>) -> PmbsResult<()> {
     // 解析命令行参数
     let path = match a.len() {
         0 => None,
         1 => Some(&a[0]),
         _ => {
-            bad_cli_arg();
-            return Err(ExitCode::from(1));
+            return Err(bad_cli_arg());
         }
     };
     // 读取环境配置
@@ -135,159 +212,442 @@ fn c_config_clean(a: Vec<String>) -> Result<(), ExitCode> {
 
     match path {
         // 执行指定配置文件
-        Some(path) => match get_clean(path) {
-            Some((config, keep, clean)) => {
-                write_clean_log(&c, &config, &keep, &clean);
-
-                // 执行清理
-                safe_rm_subvol_list(
-                    &c,
-                    clean
-                        .iter()
-                        .map(|x| x.p.to_string_lossy().to_string())
-                        .collect(),
-                );
-                Ok(())
-            }
-            None => {
-                return Err(ExitCode::from(1));
-            }
-        },
+        Some(path) => clean_config(&c, path),
         // 读取所有配置文件 (执行 pmbs config clean)
         None => run_config(&c, "clean"),
     }
 }
 
+/// 执行一个配置文件的清理
+fn clean_config(c: &ConfigEnv, path: &str) -> PmbsResult<()> {
+    let config = read_config(&PathBuf::from(path))?;
+    let dir = config.config.dir_pmbs();
+    // 快照保存目录不存在: 没有快照 (不跟随符号链接, 检查所有者)
+    let pmbs = match SafeDir::try_open_pmbs(&dir)? {
+        Some(d) => d,
+        None => {
+            debug!("dir not exist  {}", dir.to_string_lossy());
+            return Ok(());
+        }
+    };
+    // 获取锁
+    let _lock = PmbsLock::new(&pmbs, config.config.lock_timeout_s)?;
+    let (config, mut d) = decide_config(config)?;
+    let mut log = CleanLog::new(&config, &d);
+
+    // 每次清理最多删除的快照个数 (从最旧的开始删除)
+    let max_delete = config.config.max_delete_per_run.map(|x| x as usize);
+    if let Some(max) = max_delete
+        && d.clean.len() > max
+    {
+        d.clean.truncate(max);
+        log.deferred = log.clean.split_off(max);
+        info!(
+            "delete backlog  {} snapshots left for next run  {}",
+            log.deferred.len(),
+            config.path
+        );
+    }
+    write_log(c, &log.filename(), &log)?;
+
+    // 执行清理
+    let opt = RmOpt::new(&config.config);
+    let r = safe_rm_subvol_list(
+        c,
+        &config.config,
+        d.clean
+            .iter()
+            .map(|x| x.p.to_string_lossy().to_string())
+            .collect(),
+        &opt,
+    )?;
+    log.set_result(&r);
+
+    // 剩余存储空间预算
+    if let Some(budget) = &config.config.space_budget {
+        let quota = max_delete.map(|x| x - d.clean.len());
+        clean_space_budget(c, &config, budget, d.keep, quota, &mut log)?;
+    }
+
+    // 最终结果: 删除全部完成之后再报告错误
+    let result = log.finish();
+    write_log(c, &log.filename(), &log)?;
+    info!(
+        "clean done  {}  deleted = {}, failed = {}, gone = {}",
+        config.path, result.deleted, result.failed, result.gone
+    );
+    if result.failed > 0 {
+        return Err(PmbsError::Command(
+            format!(
+                "delete {} of {} snapshots  {}",
+                result.failed,
+                result.deleted + result.failed + result.gone,
+                config.path
+            ),
+            result.code.unwrap_or(1),
+        ));
+    }
+    Ok(())
+}
+
 /// 清理日志
-#[derive(Debug, Clone, Serialize)]
+///
+/// 读取时兼容旧版本的清理日志 (没有的字段为空)
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct CleanLog {
     /// 时间戳 (清理时间)
     pub t: u64,
     /// 配置
     pub config: PmbsConfigFile,
-    /// 保留的快照
+    /// 保留的快照 (不含固定的快照)
     pub keep: Vec<String>,
     /// 清理的快照
-    pub clean: Vec<String>,
+    #[serde(deserialize_with = "de_clean_list")]
+    pub clean: Vec<CleanItem>,
+    /// 固定的快照 (总是保留)
+    #[serde(default)]
+    pub pinned: Vec<String>,
+    /// 全局限制 `min_keep` 强制保留的快照 (保留规则没有保留)
+    #[serde(default)]
+    pub force_keep: Vec<String>,
+    /// 保留的快照 (含固定的快照), 以及保留规则保留的原因
+    #[serde(default)]
+    pub keep_reason: Vec<KeepItem>,
+    /// 超过 `max_delete_per_run`, 留到下次清理的快照
+    #[serde(default)]
+    pub deferred: Vec<CleanItem>,
+    /// 清理的最终结果 (清理完成之前为空)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub result: Option<CleanResult>,
 }
 
-/// 写入清理日志
-fn write_clean_log(
-    c: &ConfigEnv,
-    config: &PmbsConfigFile,
-    keep: &Vec<Snapshot>,
-    clean: &Vec<Snapshot>,
-) {
-    // 日志文件名
-    let t = get_t();
-    let filename = format!("clean-{}-{}.log.json", t, config.path);
-
-    let log = CleanLog {
-        t,
-        config: config.clone(),
-        keep: keep.iter().map(|x| x.path.clone()).collect(),
-        clean: clean.iter().map(|x| x.path.clone()).collect(),
-    };
-    let text = serde_json::to_string_pretty(&log).unwrap();
+/// 清理的最终结果: 各种结果的快照个数
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct CleanResult {
+    /// 已删除
+    pub deleted: usize,
+    /// 删除失败
+    pub failed: usize,
+    /// 快照已经不存在, 跳过
+    pub gone: usize,
+    /// 第一个删除失败的命令退出码
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub code: Option<i32>,
+}
+
+/// 保留的快照, 以及保留原因
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct KeepItem {
+    /// 快照路径, 比如 `2025/1756392923`
+    pub path: String,
+    /// 保留原因
+    pub reason: Reason,
+}
+
+/// 清理的快照, 以及清理原因
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CleanItem {
+    /// 快照时间戳
+    #[serde(skip)]
+    pub t: u64,
+    /// 快照路径, 比如 `2025/1756392923`
+    pub path: String,
+    /// 清理原因
+    #[serde(flatten)]
+    pub reason: CleanReason,
+    /// 删除结果 (尚未删除时为空)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub result: Option<RmResult>,
+}
+
+/// 清理原因
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "reason", rename_all = "snake_case")]
+pub enum CleanReason {
+    /// 保留规则 (`[[keep]]`) 没有保留
+    Rule {
+        /// 详细原因 (被遮盖, 规则用尽)
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        why: Option<Reason>,
+    },
+    /// 全局限制 `max_age`: 快照太旧
+    MaxAge,
+    /// 全局限制 `max_total`: 快照总数太多
+    MaxTotal,
+    /// 剩余存储空间不足 (`[space_budget]`)
+    SpaceBudget {
+        /// 快照所在的年龄分段
+        tier: String,
+        /// 清理时的剩余空间 (字节)
+        free: u64,
+    },
+}
+
+impl CleanLog {
+    fn new(config: &PmbsConfigFile, d: &Decision) -> Self {
+        Self {
+            t: get_t(),
+            config: config.clone(),
+            keep: d
+                .keep
+                .iter()
+                .filter(|x| !x.pinned)
+                .map(|x| x.path.clone())
+                .collect(),
+            clean: d
+                .clean
+                .iter()
+                .map(|x| CleanItem {
+                    t: x.t,
+                    path: x.path.clone(),
+                    reason: match d.get_force(x.t) {
+                        Some(Force::MaxAge) => CleanReason::MaxAge,
+                        Some(Force::MaxTotal) => CleanReason::MaxTotal,
+                        _ => CleanReason::Rule {
+                            why: d.get_reason(x.t).cloned(),
+                        },
+                    },
+                    result: None,
+                })
+                .collect(),
+            pinned: d
+                .keep
+                .iter()
+                .filter(|x| x.pinned)
+                .map(|x| x.path.clone())
+                .collect(),
+            force_keep: d
+                .keep
+                .iter()
+                .filter(|x| d.get_force(x.t) == Some(Force::MinKeep))
+                .map(|x| x.path.clone())
+                .collect(),
+            keep_reason: d
+                .keep
+                .iter()
+                .filter_map(|x| {
+                    d.get_reason(x.t).map(|r| KeepItem {
+                        path: x.path.clone(),
+                        reason: r.clone(),
+                    })
+                })
+                .collect(),
+            deferred: Vec::new(),
+            result: None,
+        }
+    }
+
+    /// 记录删除结果: (时间戳, 结果)
+    fn set_result(&mut self, r: &[(u64, RmResult)]) {
+        for (t, r) in r {
+            if let Some(i) = self.clean.iter_mut().find(|x| x.t == *t) {
+                i.result = Some(r.clone());
+            }
+        }
+    }
+
+    /// 统计最终结果
+    fn finish(&mut self) -> CleanResult {
+        let mut o = CleanResult::default();
+        for r in self.clean.iter().filter_map(|x| x.result.as_ref()) {
+            match r.status {
+                RmStatus::Deleted => o.deleted += 1,
+                RmStatus::Gone => o.gone += 1,
+                RmStatus::Failed => {
+                    o.failed += 1;
+                    o.code = o.code.or(r.code);
+                }
+            }
+        }
+        self.result = Some(o.clone());
+        o
+    }
+
+    /// 日志文件名
+    fn filename(&self) -> String {
+        format!("clean-{}-{}.log.json", self.t, self.config.path)
+    }
+}
+
+/// 写入日志文件 (json)
+fn write_log<T: Serialize>(c: &ConfigEnv, filename: &str, log: &T) -> PmbsResult<()> {
+    let text = serde_json::to_string_pretty(log).unwrap();
 
     let mut p = PathBuf::from(&c.dir_log);
     p.push(filename);
 
-    debug!("write clean log {}", p.to_string_lossy());
-    create_dir_all(&c.dir_log).unwrap();
-    write(p, text.as_bytes()).unwrap();
+    debug!("write log {}", p.to_string_lossy());
+    create_dir_all(&c.dir_log).map_err(PmbsError::io(&c.dir_log))?;
+    write(&p, text.as_bytes()).map_err(PmbsError::io(&p))
 }
 
 /// pmbs config test
-fn c_config_test(a: Vec<String>) -> Result<(), ExitCode> {
+fn c_config_test(a: Vec<String>) -> PmbsResult<()> {
     // 无命令行参数
-    if a.len() > 0 {
-        bad_cli_arg();
-        return Err(ExitCode::from(1));
+    if !a.is_empty() {
+        return Err(bad_cli_arg());
     }
     // 读取环境配置
     let c = get_env_config();
+    if is_json(&c) {
+        return config_test_json(&c);
+    }
 
-    for i in list_config(&c) {
+    for i in list_config(&c)? {
         info!("check {}", i.to_string_lossy());
 
-        match read_config(&i) {
-            Some(c) => {
-                debug!("config  {}", serde_json::to_string(&c).unwrap());
-            }
-            None => {
-                return Err(ExitCode::from(1));
-            }
-        }
+        let c = read_config(&i)?;
+        debug!("config  {}", serde_json::to_string(&c).unwrap());
     }
     Ok(())
 }
 
+/// pmbs --json config test: 检查全部配置文件, 然后返回第一个错误
+fn config_test_json(c: &ConfigEnv) -> PmbsResult<()> {
+    let mut r = Ok(());
+    let mut o = Vec::new();
+    for i in list_config(c)? {
+        let path = i.to_string_lossy().to_string();
+        o.push(match read_config(&i) {
+            Ok(c) => ConfigTestItem {
+                path,
+                ok: true,
+                error: None,
+                config: Some(c.config),
+            },
+            Err(e) => {
+                let item = ConfigTestItem {
+                    path,
+                    ok: false,
+                    error: Some(e.to_string()),
+                    config: None,
+                };
+                if r.is_ok() {
+                    r = Err(e);
+                }
+                item
+            }
+        });
+    }
+    print_json("config test", ConfigTestOutput { config: o });
+    r
+}
+
 /// pmbs config test-clean PATH
-fn c_config_test_clean(a: Vec<String>) -> Result<(), ExitCode> {
+fn c_config_test_clean(a: Vec<String>) -> PmbsResult<()> {
     // 解析命令行参数
     if a.len() != 1 {
-        bad_cli_arg();
-        return Err(ExitCode::from(1));
+        return Err(bad_cli_arg());
     }
     let path = &a[0];
 
-    match get_clean(path) {
-        Some((_, keep, clean)) => {
-            for i in keep {
-                println!("keep {}  {}", i.path, format_t_local(i.t));
-            }
-            for i in clean {
-                println!("clean {}  {}", i.path, format_t_local(i.t));
-            }
-            Ok(())
-        }
-        None => {
-            return Err(ExitCode::from(1));
-        }
+    let (config, d) = get_clean(path)?;
+    // 日历规则保留的快照: 显示时间段
+    let bucket = decide_calendar(
+        &config.config.keep,
+        d.keep.iter().chain(d.clean.iter()).cloned().collect(),
+    );
+    if is_json(&get_env_config()) {
+        // 全部快照 (按时间降序)
+        let mut list: Vec<DecisionItem> = d
+            .keep
+            .iter()
+            .map(|x| (x, "keep"))
+            .chain(d.clean.iter().map(|x| (x, "clean")))
+            .map(|(x, decision)| DecisionItem {
+                snapshot: x,
+                decision,
+                reason: d.get_reason(x.t),
+                force: d.get_force(x.t).map(|f| f.as_str()),
+                bucket: bucket
+                    .iter()
+                    .filter(|b| b.0 == x.t)
+                    .map(|b| b.1.as_str())
+                    .collect(),
+            })
+            .collect();
+        list.sort_by_key(|x| Reverse(x.snapshot.t));
+        let o = TestCleanOutput {
+            config: &config.path,
+            snapshot: list,
+        };
+        print_json("config test-clean", o);
+        return Ok(());
     }
+    // 全局限制强制 保留/清理 的原因
+    let force = |t: u64| match d.get_force(t) {
+        Some(f) => format!("  *{}", f.as_str()),
+        None => String::new(),
+    };
+    for i in &d.keep {
+        let pinned = if i.pinned { "  *pinned" } else { "" };
+        let b: Vec<&str> = bucket
+            .iter()
+            .filter(|x| x.0 == i.t)
+            .map(|x| x.1.as_str())
+            .collect();
+        let b = if b.is_empty() {
+            String::new()
+        } else {
+            format!("  {}", b.join(", "))
+        };
+        println!(
+            "keep {}  {}{}{}{}",
+            i.path,
+            format_t_local(i.t),
+            pinned,
+            b,
+            force(i.t)
+        );
+    }
+    for i in &d.clean {
+        println!("clean {}  {}{}", i.path, format_t_local(i.t), force(i.t));
+    }
+    Ok(())
 }
 
+/// 清理列表: (配置, 清理决定)
+type CleanList = (PmbsConfigFile, Decision);
+
 /// 获取清理列表
-fn get_clean(path: &str) -> Option<(PmbsConfigFile, Vec<Snapshot>, Vec<Snapshot>)> {
+fn get_clean(path: &str) -> PmbsResult<CleanList> {
     // 加载配置文件
-    match read_config(&PathBuf::from(path)) {
-        Some(config) => {
-            debug!("config  {}", serde_json::to_string(&config).unwrap());
+    decide_config(read_config(&PathBuf::from(path))?)
+}
 
-            // 列出全部快照
-            let snapshot = ls_snapshot(&config.config.subvol);
-            let total = snapshot.len();
-            // 检查清理
-            let (keep, clean) = decide(config.config.keep.clone(), snapshot);
-
-            debug!(
-                "total = {}, keep = {}, clean = {}",
-                total,
-                keep.len(),
-                clean.len()
-            );
-            // 检查错误
-            if total != (keep.len() + clean.len()) {
-                panic!(
-                    "bad clean, total = {}, keep = {}, clean = {}",
-                    total,
-                    keep.len(),
-                    clean.len()
-                );
-            }
-            Some((config, keep, clean))
-        }
-        None => None,
+/// 列出全部快照, 并检查清理
+fn decide_config(config: PmbsConfigFile) -> PmbsResult<CleanList> {
+    debug!("config  {}", serde_json::to_string(&config).unwrap());
+
+    // 列出全部快照
+    let snapshot = ls_snapshot(&config.config.dir_pmbs())?;
+    let total = snapshot.len();
+    // 检查清理
+    let d = decide(&config.config, snapshot);
+
+    debug!(
+        "total = {}, keep = {}, clean = {}",
+        total,
+        d.keep.len(),
+        d.clean.len()
+    );
+    // 检查错误
+    if total != (d.keep.len() + d.clean.len()) {
+        return Err(PmbsError::Safety(format!(
+            "bad clean, total = {}, keep = {}, clean = {}",
+            total,
+            d.keep.len(),
+            d.clean.len()
+        )));
     }
+    Ok((config, d))
 }
 
 /// pmbs config *
-fn c_config(a: Vec<String>) -> Result<(), ExitCode> {
+fn c_config(a: Vec<String>) -> PmbsResult<()> {
     // 解析命令行参数
-    if a.len() < 1 {
-        bad_cli_arg();
-        return Err(ExitCode::from(1));
+    if a.is_empty() {
+        return Err(bad_cli_arg());
     }
     // 第 1 个参数: 命令
     let r: Vec<String> = (&a[1..]).into();
@@ -296,21 +656,41 @@ fn c_config(a: Vec<String>) -> Result<(), ExitCode> {
         "clean" => c_config_clean(r),
         "test" => c_config_test(r),
         "test-clean" => c_config_test_clean(r),
+        "explain" => c_config_explain(r),
+        "simulate" => c_config_simulate(r),
+        "migrate" => c_config_migrate(r),
 
-        _ => {
-            bad_cli_arg();
-            Err(ExitCode::from(1))
-        }
+        _ => Err(bad_cli_arg()),
     }
 }
 
 /// 命令行执行入口
-pub fn main(a: Vec<String>) -> Result<(), ExitCode> {
+///
+/// 错误对应的退出码见 [`PmbsError::exit_code`]
+pub fn main(a: Vec<String>) -> ExitCode {
+    match run(a) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            match &e {
+                PmbsError::Arg(_) => eprintln!("ERROR: {}", e),
+                _ => error!("{}", e),
+            }
+            ExitCode::from(e.exit_code())
+        }
+    }
+}
+
+fn run(a: Vec<String>) -> PmbsResult<()> {
     // 命令行参数解析处理
-    if a.len() > 0 {
+    if !a.is_empty() {
         // 第 1 个参数: 命令
         let r: Vec<String> = (&a[1..]).into();
         match a[0].as_str() {
+            // 全局参数: 在命令之前
+            "--json" => {
+                set_json();
+                run(r)
+            }
             "--help" => {
                 help::help_en();
                 Ok(())
@@ -322,16 +702,20 @@ pub fn main(a: Vec<String>) -> Result<(), ExitCode> {
 
             "snapshot" => c_snapshot(r),
             "ls" => c_ls(r),
+            "pin" => c_pin(r),
+            "unpin" => c_unpin(r),
+            "find" => c_find(r),
 
+            "log" => c_log(r),
+            "fsck" => c_fsck(r),
             "config" => c_config(r),
 
-            _ => {
-                eprintln!("ERROR: Bad command `{}`, try --help", a[0]);
-                Err(ExitCode::from(1))
-            }
+            _ => Err(PmbsError::Arg(format!(
+                "Bad command `{}`, try --help",
+                a[0]
+            ))),
         }
     } else {
-        eprintln!("ERROR: Bad command, try --help");
-        Err(ExitCode::from(1))
+        Err(PmbsError::Arg("Bad command, try --help".into()))
     }
 }
@@ -0,0 +1,159 @@
+//! 机器可读的 JSON 输出 (`pmbs --json`, `PMBS_OUTPUT=json`)
+//!
+//! 每个命令输出一个 JSON 文档, 含有 `version` (格式版本) 和 `kind` (命令).
+//! 增加字段不改变版本; 删除字段, 或者修改字段的含义时, 增加版本.
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use serde::Serialize;
+
+use crate::{
+    clean::{Reason, Snapshot},
+    config::{ConfigEnv, PmbsConfig},
+};
+
+/// JSON 输出格式的版本
+pub const OUTPUT_VERSION: u32 = 1;
+/// PMBS_OUTPUT=json
+pub const OUTPUT_JSON: &str = "json";
+
+/// 命令行全局参数 `--json`
+static FLAG_JSON: AtomicBool = AtomicBool::new(false);
+
+/// 设置全局参数 `--json`
+pub fn set_json() {
+    FLAG_JSON.store(true, Ordering::Relaxed);
+}
+
+/// 是否使用 JSON 输出
+pub fn is_json(c: &ConfigEnv) -> bool {
+    FLAG_JSON.load(Ordering::Relaxed) || c.output == OUTPUT_JSON
+}
+
+/// 输出的 JSON 文档
+#[derive(Serialize)]
+struct Doc<'a, T: Serialize> {
+    /// 格式版本
+    version: u32,
+    /// 命令, 比如 `ls`, `config test-clean`
+    kind: &'a str,
+    #[serde(flatten)]
+    data: T,
+}
+
+/// 输出 JSON 文档
+pub fn print_json<T: Serialize>(kind: &str, data: T) {
+    let d = Doc {
+        version: OUTPUT_VERSION,
+        kind,
+        data,
+    };
+    println!("{}", serde_json::to_string_pretty(&d).unwrap());
+}
+
+/// pmbs ls
+#[derive(Serialize)]
+pub struct LsOutput<'a> {
+    /// subvol 路径
+    pub subvol: &'a str,
+    /// 快照保存目录
+    pub dir: String,
+    /// 全部快照 (按时间降序)
+    pub snapshot: &'a [Snapshot],
+}
+
+/// pmbs config test: 一个配置文件
+#[derive(Serialize)]
+pub struct ConfigTestItem {
+    /// 配置文件路径
+    pub path: String,
+    /// 是否没有错误
+    pub ok: bool,
+    /// 错误信息
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    /// 配置内容
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub config: Option<PmbsConfig>,
+}
+
+/// pmbs config test
+#[derive(Serialize)]
+pub struct ConfigTestOutput {
+    /// 全部配置文件
+    pub config: Vec<ConfigTestItem>,
+}
+
+/// pmbs config test-clean
+#[derive(Serialize)]
+pub struct TestCleanOutput<'a> {
+    /// 配置文件名
+    pub config: &'a str,
+    /// 全部快照 (按时间降序)
+    pub snapshot: Vec<DecisionItem<'a>>,
+}
+
+/// pmbs config test-clean: 一个快照的清理决定
+#[derive(Serialize)]
+pub struct DecisionItem<'a> {
+    #[serde(flatten)]
+    pub snapshot: &'a Snapshot,
+    /// `keep` 或者 `clean`
+    pub decision: &'static str,
+    /// 保留规则 保留/清理 的原因
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reason: Option<&'a Reason>,
+    /// 全局限制强制 保留/清理: `max_age`, `max_total`, `min_keep`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub force: Option<&'static str>,
+    /// 日历规则保留的时间段, 比如 `daily 2025-08-28`
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub bucket: Vec<&'a str>,
+}
+
+#[cfg(test)]
+mod test {
+    use serde_json::{Value, json};
+
+    use super::*;
+
+    /// 字段名是稳定的 (修改时需要增加 OUTPUT_VERSION)
+    #[test]
+    fn schema() {
+        let mut s = Snapshot::new_t(1756392923);
+        s.latest = true;
+        let d = Doc {
+            version: OUTPUT_VERSION,
+            kind: "config test-clean",
+            data: TestCleanOutput {
+                config: "home.toml",
+                snapshot: vec![DecisionItem {
+                    snapshot: &s,
+                    decision: "keep",
+                    reason: Some(&Reason::Rule { rule: 0, slot: 1 }),
+                    force: None,
+                    bucket: vec![],
+                }],
+            },
+        };
+        let v: Value = serde_json::to_value(&d).unwrap();
+        assert_eq!(
+            v,
+            json!({
+                "version": 1,
+                "kind": "config test-clean",
+                "config": "home.toml",
+                "snapshot": [{
+                    "path": "2025/1756392923",
+                    "year": 2025,
+                    "t": 1756392923,
+                    "latest": true,
+                    "full_path": "2025/1756392923",
+                    "meta": null,
+                    "pinned": false,
+                    "decision": "keep",
+                    "reason": {"kind": "rule", "rule": 0, "slot": 1}
+                }]
+            })
+        );
+    }
+}
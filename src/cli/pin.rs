@@ -0,0 +1,139 @@
+//! pmbs pin / pmbs unpin
+use std::{path::Path, str::FromStr};
+
+use chrono::DateTime;
+use log::info;
+
+use crate::{
+    clean::{Pin, Snapshot, ls_snapshot, read_pin, write_pin},
+    config::{find_config, get_env_config, parse_time},
+    error::{PmbsError, PmbsResult},
+    snapshot::{PmbsLock, SafeDir, format_t_local, get_t, parse_snapshot_t},
+};
+
+use super::help::bad_cli_arg;
+
+/// 解析 --until 时间: 时间长度 (比如 `7d`), 时间戳, 或者 RFC 3339 格式
+pub fn parse_until(s: &str, now: u64) -> Option<u64> {
+    if let Some(d) = parse_time(s) {
+        return Some(now + d);
+    }
+    if let Ok(t) = u64::from_str(s) {
+        return Some(t);
+    }
+    match DateTime::parse_from_rfc3339(s) {
+        Ok(t) => u64::try_from(t.timestamp()).ok(),
+        Err(_) => None,
+    }
+}
+
+/// 查找快照, 接受 `2025/1756392923`, `1756392923` 或者完整路径
+pub fn find_snapshot(dir: &Path, name: &str) -> PmbsResult<Option<Snapshot>> {
+    let t = match parse_snapshot_t(name) {
+        Some(t) => t,
+        None => {
+            return Ok(None);
+        }
+    };
+    Ok(ls_snapshot(dir)?.into_iter().find(|x| x.t == t))
+}
+
+/// pmbs pin SUBVOL SNAPSHOT [--until TIME]
+pub fn c_pin(a: Vec<String>) -> PmbsResult<()> {
+    // 解析命令行参数
+    let now = get_t();
+    let until = match a.len() {
+        2 => None,
+        4 if a[2] == "--until" => match parse_until(&a[3], now) {
+            Some(t) => Some(t),
+            None => {
+                return Err(PmbsError::Arg(format!("bad time  {}", a[3])));
+            }
+        },
+        _ => {
+            return Err(bad_cli_arg());
+        }
+    };
+    let (subvol, name) = (&a[0], &a[1]);
+    let config = find_config(&get_env_config(), subvol, false)?;
+    let dir = config.dir_pmbs();
+    let pmbs = SafeDir::open_pmbs(&dir, false)?;
+    let _lock = PmbsLock::new(&pmbs, config.lock_timeout_s)?;
+
+    let s = match find_snapshot(&dir, name)? {
+        Some(s) => s,
+        None => {
+            return Err(PmbsError::Arg(format!("snapshot not found  {}", name)));
+        }
+    };
+    let mut list = read_pin(&dir);
+    // 顺便清理已经过期的固定
+    list.pin.retain(|x| x.is_active(now));
+    list.add(Pin {
+        path: s.path.clone(),
+        t: now,
+        until,
+    });
+    write_pin(&pmbs, &list)?;
+
+    match until {
+        Some(t) => info!("pin {}  until {}", s.path, format_t_local(t)),
+        None => info!("pin {}", s.path),
+    }
+    Ok(())
+}
+
+/// pmbs unpin SUBVOL SNAPSHOT
+pub fn c_unpin(a: Vec<String>) -> PmbsResult<()> {
+    // 解析命令行参数
+    if a.len() != 2 {
+        return Err(bad_cli_arg());
+    }
+    let (subvol, name) = (&a[0], &a[1]);
+
+    let config = find_config(&get_env_config(), subvol, false)?;
+    let dir = config.dir_pmbs();
+    let pmbs = SafeDir::open_pmbs(&dir, false)?;
+    let _lock = PmbsLock::new(&pmbs, config.lock_timeout_s)?;
+    let mut list = read_pin(&dir);
+    // 快照可能已经不存在 (被手动删除)
+    let path = match find_snapshot(&dir, name)? {
+        Some(s) => s.path,
+        None => {
+            let t = parse_snapshot_t(name);
+            match list
+                .pin
+                .iter()
+                .find(|x| t.is_some() && parse_snapshot_t(&x.path) == t)
+            {
+                Some(p) => p.path.clone(),
+                None => name.to_string(),
+            }
+        }
+    };
+    if !list.remove(&path) {
+        return Err(PmbsError::Arg(format!("snapshot not pinned  {}", path)));
+    }
+    write_pin(&pmbs, &list)?;
+
+    info!("unpin {}", path);
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn until() {
+        let now = 1756392923;
+        assert_eq!(parse_until("1d", now), Some(now + 86400));
+        assert_eq!(parse_until("1756400000", now), Some(1756400000));
+        assert_eq!(
+            parse_until("2025-09-01T00:00:00+08:00", now),
+            Some(1756656000)
+        );
+        assert_eq!(parse_until("tomorrow", now), None);
+        assert_eq!(parse_until("", now), None);
+    }
+}
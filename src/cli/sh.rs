@@ -1,15 +1,110 @@
 //! 调用执行命令 (shell)
-use std::process::Command;
+use std::{
+    os::unix::process::ExitStatusExt,
+    process::{Command, ExitStatus},
+    thread::sleep,
+    time::{Duration, Instant},
+};
 
-use log::{error, info};
+use log::{debug, error, info};
+
+use crate::error::{PmbsError, PmbsResult};
+
+/// 命令执行超时的退出码 (同 timeout 命令)
+pub const CODE_TIMEOUT: i32 = 124;
+
+/// 检查命令的退出状态
+fn check_status(c: &Command, s: ExitStatus) -> PmbsResult<()> {
+    match s.code() {
+        Some(0) => Ok(()),
+        Some(code) => Err(PmbsError::Command(format!("{:?}", c), code)),
+        // 进程被信号终止
+        None => Err(PmbsError::Signal(
+            format!("{:?}", c),
+            s.signal().unwrap_or(0),
+        )),
+    }
+}
 
 /// 执行 shell 命令
-pub fn sh_run(mut c: Command) -> i32 {
+pub fn sh_run(mut c: Command) -> PmbsResult<()> {
     info!("run {:?}", c);
 
-    let code = c.status().unwrap().code().unwrap();
-    if 0 != code {
-        error!("exit code {}", code);
+    let s = c.status().map_err(PmbsError::io(c.get_program()))?;
+    check_status(&c, s)
+}
+
+/// 执行命令, 并获取输出 (stdout)
+pub fn sh_output(mut c: Command) -> PmbsResult<String> {
+    debug!("run {:?}", c);
+
+    let o = c.output().map_err(PmbsError::io(c.get_program()))?;
+    if !o.status.success() {
+        error!("{}", String::from_utf8_lossy(&o.stderr).trim());
+    }
+    check_status(&c, o.status)?;
+    Ok(String::from_utf8_lossy(&o.stdout).to_string())
+}
+
+/// 执行命令, 如果超时 (秒) 则杀死进程
+pub fn sh_run_timeout(mut c: Command, timeout: u64) -> PmbsResult<()> {
+    info!("run {:?}", c);
+
+    let mut child = c.spawn().map_err(PmbsError::io(c.get_program()))?;
+    let start = Instant::now();
+    loop {
+        if let Some(s) = child.try_wait().map_err(PmbsError::io(c.get_program()))? {
+            return check_status(&c, s);
+        }
+        if start.elapsed() >= Duration::from_secs(timeout) {
+            error!("timeout {}s, kill {}", timeout, child.id());
+            let _ = child.kill();
+            let _ = child.wait();
+            return Err(PmbsError::Command(format!("{:?}", c), CODE_TIMEOUT));
+        }
+        sleep(Duration::from_millis(100));
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn run_timeout() {
+        assert!(sh_run_timeout(Command::new("true"), 10).is_ok());
+        assert!(matches!(
+            sh_run_timeout(Command::new("false"), 10),
+            Err(PmbsError::Command(_, 1))
+        ));
+
+        let mut c = Command::new("sleep");
+        c.arg("10");
+        assert!(matches!(
+            sh_run_timeout(c, 0),
+            Err(PmbsError::Command(_, CODE_TIMEOUT))
+        ));
+
+        let mut c = Command::new("sh");
+        c.arg("-c").arg("kill -9 $$");
+        assert!(matches!(
+            sh_run_timeout(c, 10),
+            Err(PmbsError::Signal(_, 9))
+        ));
+
+        let c = Command::new("/nonexistent/pmbs-test");
+        assert!(matches!(sh_run_timeout(c, 10), Err(PmbsError::Io(..))));
+    }
+
+    #[test]
+    fn run() {
+        assert!(sh_run(Command::new("true")).is_ok());
+        let mut c = Command::new("sh");
+        c.arg("-c").arg("kill -15 $$");
+        assert!(matches!(sh_run(c), Err(PmbsError::Signal(_, 15))));
+
+        let mut c = Command::new("echo");
+        c.arg("666");
+        assert_eq!(sh_output(c).unwrap(), "666\n");
     }
-    code
 }
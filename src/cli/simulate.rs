@@ -0,0 +1,119 @@
+//! pmbs config simulate: 在模拟的时间线上检查保留规则
+use std::{path::PathBuf, str::FromStr};
+
+use crate::{
+    clean::{SimulateOpt, simulate},
+    config::{parse_duration, read_config},
+    error::{PmbsError, PmbsResult},
+    snapshot::{format_t_local, get_t},
+};
+
+use super::help::bad_cli_arg;
+
+/// 解析命令行参数中的时间, 比如 `1m`, `2s` (允许 `0s`)
+fn parse_arg_time(s: &str) -> PmbsResult<u64> {
+    parse_duration(s).map_err(|e| PmbsError::Arg(format!("bad time  {}", e)))
+}
+
+/// 解析 `--gaps START+LEN,...`, 比如 `3d+12h,10d+2d`
+fn parse_gaps(s: &str) -> PmbsResult<Vec<(u64, u64)>> {
+    s.split(',')
+        .map(|x| match x.split_once('+') {
+            Some((start, len)) => Ok((parse_arg_time(start)?, parse_arg_time(len)?)),
+            None => Err(PmbsError::Arg(format!("bad gap  {}", x))),
+        })
+        .collect()
+}
+
+/// 格式化时间长度, 比如 `2d 3h 5m`
+fn format_s(mut s: u64) -> String {
+    let mut o: Vec<String> = Vec::new();
+    for (n, u) in [(86400, "d"), (3600, "h"), (60, "m"), (1, "s")] {
+        if s >= n {
+            o.push(format!("{}{}", s / n, u));
+            s %= n;
+        }
+    }
+    if o.is_empty() {
+        "0s".into()
+    } else {
+        o.join(" ")
+    }
+}
+
+/// pmbs config simulate PATH [--days N] [--interval TIME] [--jitter TIME] [--gaps START+LEN,...]
+pub fn c_config_simulate(a: Vec<String>) -> PmbsResult<()> {
+    // 解析命令行参数
+    if a.is_empty() || a.len() % 2 != 1 {
+        return Err(bad_cli_arg());
+    }
+    let mut opt = SimulateOpt {
+        end: get_t(),
+        ..Default::default()
+    };
+    for i in a[1..].chunks(2) {
+        let v = &i[1];
+        match i[0].as_str() {
+            "--days" => {
+                opt.days =
+                    u64::from_str(v).map_err(|_| PmbsError::Arg(format!("bad days  {}", v)))?;
+            }
+            "--interval" => opt.interval = parse_arg_time(v)?,
+            "--jitter" => opt.jitter = parse_arg_time(v)?,
+            "--gaps" => opt.gaps = parse_gaps(v)?,
+            "--clean-every" => opt.clean_every = parse_arg_time(v)?,
+            "--seed" => {
+                opt.seed =
+                    u64::from_str(v).map_err(|_| PmbsError::Arg(format!("bad seed  {}", v)))?;
+            }
+            _ => {
+                return Err(bad_cli_arg());
+            }
+        }
+    }
+    let config = read_config(&PathBuf::from(&a[0]))?.config;
+
+    let r = simulate(&config, &opt)?;
+    println!(
+        "simulate {} days  interval {}s  jitter {}s  clean every {}s",
+        opt.days, opt.interval, opt.jitter, opt.clean_every
+    );
+    println!("made {}  keep {}  peak {}", r.made, r.keep.len(), r.peak);
+    for (day, n) in &r.count {
+        println!("day {}  count {}", day, n);
+    }
+    for (band, n) in r.age_band() {
+        println!("age {}  {}", band, n);
+    }
+    if let Some((gap, from, to)) = r.max_gap() {
+        println!(
+            "max gap {}  {} -> {}",
+            format_s(gap),
+            format_t_local(from),
+            format_t_local(to)
+        );
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn parse_arg() {
+        assert_eq!(parse_arg_time("2s").unwrap(), 2);
+        assert_eq!(parse_arg_time("1m").unwrap(), 60);
+        assert!(parse_arg_time("1x").is_err());
+        // --jitter 0s, --gaps 0s+1d
+        assert_eq!(parse_arg_time("0s").unwrap(), 0);
+        assert_eq!(parse_gaps("0s+1d").unwrap(), vec![(0, 86400)]);
+        assert_eq!(
+            parse_gaps("3d+12h,10d+30s").unwrap(),
+            vec![(3 * 86400, 12 * 3600), (10 * 86400, 30)]
+        );
+        assert!(parse_gaps("3d").is_err());
+        assert_eq!(format_s(86400 + 3600 * 11 + 45 * 60), "1d 11h 45m");
+        assert_eq!(format_s(0), "0s");
+    }
+}
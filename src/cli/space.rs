@@ -0,0 +1,180 @@
+//! 创建快照之前检查剩余存储空间
+use log::{error, info, warn};
+use serde::Serialize;
+
+use crate::{
+    backend::get_backend,
+    clean::{RmOpt, Snapshot, budget_pick, safe_rm_subvol_list},
+    config::{ConfigEnv, PmbsConfigFile, PmbsConfigSpaceBudget, SpacePolicy},
+    error::PmbsResult,
+    snapshot::{Space, get_space, get_t},
+};
+
+use super::{CleanItem, CleanLog, CleanReason, clean_config, write_log};
+
+/// 剩余空间不足日志
+#[derive(Debug, Clone, Serialize)]
+pub struct SpaceLog {
+    /// 时间戳 (检查时间)
+    pub t: u64,
+    /// 配置文件名
+    pub config: String,
+    /// 目标 subvol 路径
+    pub subvol: String,
+    /// 处理方式
+    pub policy: SpacePolicy,
+    /// 检查时的存储空间
+    pub space: Space,
+    /// 紧急清理之后的存储空间
+    pub space_after_clean: Option<Space>,
+    /// 是否继续创建快照
+    pub snapshot: bool,
+}
+
+/// 检查剩余存储空间, 并根据配置处理
+///
+/// 返回: 是否继续创建快照
+pub fn check_space(c: &ConfigEnv, path: &str, config: &PmbsConfigFile) -> bool {
+    let rule = match &config.config.space {
+        Some(rule) => rule,
+        // 未配置, 不检查
+        None => {
+            return true;
+        }
+    };
+    let subvol = &config.config.subvol;
+    let space = match get_space(subvol) {
+        Some(space) => space,
+        // 无法获取存储空间, 创建快照更优先
+        None => {
+            return true;
+        }
+    };
+    if !space.is_low(rule) {
+        return true;
+    }
+    warn!(
+        "low free space !  {}  free = {} ({:.1}%), total = {}",
+        subvol,
+        space.free,
+        space.free_percent(),
+        space.total
+    );
+
+    let mut log = SpaceLog {
+        t: get_t(),
+        config: config.path.clone(),
+        subvol: subvol.clone(),
+        policy: rule.policy,
+        space,
+        space_after_clean: None,
+        snapshot: true,
+    };
+    match rule.policy {
+        SpacePolicy::Warn => {}
+        SpacePolicy::Skip => {
+            warn!("skip snapshot  {}", subvol);
+            log.snapshot = false;
+        }
+        SpacePolicy::Clean => {
+            info!("emergency clean  {}", path);
+            if let Err(e) = clean_config(c, path) {
+                error!("emergency clean failed  {}  {}", path, e);
+            }
+            log.space_after_clean = get_space(subvol);
+            if let Some(s) = &log.space_after_clean
+                && s.is_low(rule)
+            {
+                warn!("still low free space after clean !  {}", subvol);
+            }
+        }
+    }
+
+    let filename = format!("space-{}-{}.log.json", log.t, config.path);
+    if let Err(e) = write_log(c, &filename, &log) {
+        error!("{}", e);
+    }
+    log.snapshot
+}
+
+/// 剩余存储空间预算: 按照保留规则清理之后, 剩余空间仍然不足, 则继续清理
+///
+/// 每次删除一个价值最低的快照, 直到剩余空间恢复, 或者只剩下 floor,
+/// 或者达到本次清理最多删除的个数 `quota`.
+/// 每次删除之前先更新清理日志.
+/// 删除失败的快照记录在清理日志中, 不再选择, 继续清理.
+pub fn clean_space_budget(
+    c: &ConfigEnv,
+    config: &PmbsConfigFile,
+    budget: &PmbsConfigSpaceBudget,
+    mut keep: Vec<Snapshot>,
+    mut quota: Option<usize>,
+    log: &mut CleanLog,
+) -> PmbsResult<()> {
+    let subvol = &config.config.subvol;
+    let dir = config.config.dir_pmbs();
+    let b = get_backend(c);
+    loop {
+        // 等待删除的快照释放存储空间
+        b.sync(&dir)?;
+        let space = match get_space(subvol) {
+            Some(space) => space,
+            None => {
+                return Ok(());
+            }
+        };
+        if !space.is_below(budget.min_free_bytes, budget.min_free_percent) {
+            return Ok(());
+        }
+        if quota == Some(0) {
+            warn!(
+                "space budget stop (max_delete_per_run)  {}  free = {} ({:.1}%)",
+                subvol,
+                space.free,
+                space.free_percent()
+            );
+            return Ok(());
+        }
+        let (s, tier) = match budget_pick(&keep, budget.floor) {
+            Some(x) => x,
+            None => {
+                warn!(
+                    "space budget floor reached !  {}  free = {} ({:.1}%)",
+                    subvol,
+                    space.free,
+                    space.free_percent()
+                );
+                return Ok(());
+            }
+        };
+        warn!(
+            "space budget clean  {}  tier {}  free = {} ({:.1}%)",
+            s.path,
+            tier,
+            space.free,
+            space.free_percent()
+        );
+
+        log.keep.retain(|x| *x != s.path);
+        log.clean.push(CleanItem {
+            t: s.t,
+            path: s.path.clone(),
+            reason: CleanReason::SpaceBudget {
+                tier,
+                free: space.free,
+            },
+            result: None,
+        });
+        write_log(c, &log.filename(), log)?;
+
+        let r = safe_rm_subvol_list(
+            c,
+            &config.config,
+            vec![s.p.to_string_lossy().to_string()],
+            &RmOpt::default(),
+        )?;
+        log.set_result(&r);
+        keep.retain(|x| x.t != s.t);
+        quota = quota.map(|x| x - 1);
+    }
+}
@@ -4,16 +4,28 @@ use std::env;
 use log::debug;
 
 // PMBS_DIR_ETC=/etc/pmbs
-const ENV_PMBS_DIR_ETC: &'static str = "PMBS_DIR_ETC";
-const DEFAULT_PMBS_DIR_ETC: &'static str = "/etc/pmbs";
+const ENV_PMBS_DIR_ETC: &str = "PMBS_DIR_ETC";
+const DEFAULT_PMBS_DIR_ETC: &str = "/etc/pmbs";
 
 // PMBS_DIR_LOG=/var/log/pmbs
-const ENV_PMBS_DIR_LOG: &'static str = "PMBS_DIR_LOG";
-const DEFAULT_PMBS_DIR_LOG: &'static str = "/var/log/pmbs";
+const ENV_PMBS_DIR_LOG: &str = "PMBS_DIR_LOG";
+const DEFAULT_PMBS_DIR_LOG: &str = "/var/log/pmbs";
 
 // PMBS_BIN_BTRFS=btrfs
-const ENV_PMBS_BIN_BTRFS: &'static str = "PMBS_BIN_BTRFS";
-const DEFAULT_PMBS_BIN_BTRFS: &'static str = "btrfs";
+const ENV_PMBS_BIN_BTRFS: &str = "PMBS_BIN_BTRFS";
+const DEFAULT_PMBS_BIN_BTRFS: &str = "btrfs";
+
+// PMBS_BIN_IONICE=ionice (空字符串表示不使用)
+const ENV_PMBS_BIN_IONICE: &str = "PMBS_BIN_IONICE";
+const DEFAULT_PMBS_BIN_IONICE: &str = "ionice";
+
+// PMBS_BACKEND=btrfs
+const ENV_PMBS_BACKEND: &str = "PMBS_BACKEND";
+const DEFAULT_PMBS_BACKEND: &str = "btrfs";
+
+// PMBS_OUTPUT=text
+const ENV_PMBS_OUTPUT: &str = "PMBS_OUTPUT";
+const DEFAULT_PMBS_OUTPUT: &str = "text";
 
 /// 环境变量配置
 #[derive(Debug, Clone)]
@@ -24,6 +36,12 @@ pub struct ConfigEnv {
     pub dir_log: String,
     /// btrfs 命令
     pub bin_btrfs: String,
+    /// ionice 命令: 使用空闲 IO 优先级删除快照 (空字符串表示不使用)
+    pub bin_ionice: String,
+    /// 快照后端: btrfs, dir
+    pub backend: String,
+    /// 命令输出格式: text, json
+    pub output: String,
 }
 
 impl ConfigEnv {
@@ -33,10 +51,19 @@ impl ConfigEnv {
             dir_etc: env::var(ENV_PMBS_DIR_ETC).unwrap_or(DEFAULT_PMBS_DIR_ETC.into()),
             dir_log: env::var(ENV_PMBS_DIR_LOG).unwrap_or(DEFAULT_PMBS_DIR_LOG.into()),
             bin_btrfs: env::var(ENV_PMBS_BIN_BTRFS).unwrap_or(DEFAULT_PMBS_BIN_BTRFS.into()),
+            bin_ionice: env::var(ENV_PMBS_BIN_IONICE).unwrap_or(DEFAULT_PMBS_BIN_IONICE.into()),
+            backend: env::var(ENV_PMBS_BACKEND).unwrap_or(DEFAULT_PMBS_BACKEND.into()),
+            output: env::var(ENV_PMBS_OUTPUT).unwrap_or(DEFAULT_PMBS_OUTPUT.into()),
         }
     }
 }
 
+impl Default for ConfigEnv {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 /// 获取环境变量配置
 pub fn get_env_config() -> ConfigEnv {
     let c = ConfigEnv::new();
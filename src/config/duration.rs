@@ -0,0 +1,155 @@
+//! 解析时间长度 (比如 `1h30m`), 转换为秒
+//!
+//! 格式: 一个或多个 `数字+单位`, 单位必须从大到小, 不能重复.
+//! 数字允许使用 `_` 分隔, 比如 `1_000m`. 之间可以有空格, 比如 `1d 12h`.
+//!
+//! | 单位 | 含义 | 秒       |
+//! | :--- | :--- | -------: |
+//! | `y`  | 年   | 365 天   |
+//! | `mo` | 月   | 30 天    |
+//! | `w`  | 周   | 7 天     |
+//! | `d`  | 天   | 86400    |
+//! | `h`  | 小时 | 3600     |
+//! | `m`  | 分钟 | 60       |
+//! | `s`  | 秒   | 1        |
+
+/// 时间单位: (名称, 秒), 从大到小
+const UNIT: [(&str, u64); 7] = [
+    ("y", 86400 * 365),
+    ("mo", 86400 * 30),
+    ("w", 86400 * 7),
+    ("d", 86400),
+    ("h", 3600),
+    ("m", 60),
+    ("s", 1),
+];
+
+/// 解析时间长度, 转换为秒
+///
+/// 允许 0 (比如 `0s`, 用于命令行参数); 配置文件中不允许 0, 见 [`parse_nonzero`].
+///
+/// 错误信息指出出错的位置, 比如 `bad unit "x" at 5 in "1h30x"`
+pub fn parse_duration(s: &str) -> Result<u64, String> {
+    let b = s.as_bytes();
+    let mut i = 0;
+    let mut total: u64 = 0;
+    // 上一个单位 (在 UNIT 中的序号), 用于检查单位顺序
+    let mut last: Option<usize> = None;
+
+    // 错误信息: 位置从 1 开始
+    let err = |msg: String, at: usize| format!("{} at {} in \"{}\"", msg, at + 1, s);
+    loop {
+        while i < b.len() && b[i] == b' ' {
+            i += 1;
+        }
+        if i >= b.len() {
+            break;
+        }
+
+        // 数字
+        let start = i;
+        while i < b.len() && (b[i].is_ascii_digit() || (i > start && b[i] == b'_')) {
+            i += 1;
+        }
+        if i == start {
+            return Err(err(format!("expect number \"{}\"", &s[i..]), i));
+        }
+        let n: u64 = s[start..i]
+            .replace('_', "")
+            .parse()
+            .map_err(|_| err(format!("overflow \"{}\"", &s[start..i]), start))?;
+
+        // 单位
+        let u = i;
+        while i < b.len() && b[i].is_ascii_alphabetic() {
+            i += 1;
+        }
+        let unit = &s[u..i];
+        if unit.is_empty() {
+            return Err(err(format!("missing unit after \"{}\"", &s[start..i]), u));
+        }
+        let k = match UNIT.iter().position(|x| x.0 == unit) {
+            Some(k) => k,
+            None => {
+                return Err(err(format!("bad unit \"{}\"", unit), u));
+            }
+        };
+        if let Some(l) = last
+            && k <= l
+        {
+            return Err(err(format!("unit out of order \"{}\"", unit), u));
+        }
+        last = Some(k);
+
+        // 检查溢出
+        total = n
+            .checked_mul(UNIT[k].1)
+            .and_then(|x| x.checked_add(total))
+            .ok_or_else(|| err(format!("overflow \"{}\"", &s[start..i]), start))?;
+    }
+
+    if last.is_none() {
+        return Err(format!("empty time \"{}\"", s));
+    }
+    Ok(total)
+}
+
+/// 解析时间长度, 不允许 0 (配置文件中的时间)
+pub fn parse_nonzero(s: &str) -> Result<u64, String> {
+    match parse_duration(s)? {
+        0 => Err(format!("zero time \"{}\"", s)),
+        n => Ok(n),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn parse() {
+        assert_eq!(parse_duration("90s"), Ok(90));
+        assert_eq!(parse_duration("1m"), Ok(60));
+        assert_eq!(parse_duration("1h30m"), Ok(5400));
+        assert_eq!(parse_duration("1d 12h"), Ok(86400 + 12 * 3600));
+        assert_eq!(parse_duration("2w"), Ok(14 * 86400));
+        assert_eq!(parse_duration("1mo"), Ok(30 * 86400));
+        assert_eq!(parse_duration("1y1mo1w"), Ok((365 + 30 + 7) * 86400));
+        assert_eq!(parse_duration("1_000m"), Ok(60_000));
+        assert_eq!(parse_duration("1h0m"), Ok(3600));
+        // 命令行参数允许 0
+        assert_eq!(parse_duration("0s"), Ok(0));
+        assert_eq!(parse_duration("0m 0s"), Ok(0));
+        assert_eq!(parse_nonzero("1m"), Ok(60));
+    }
+
+    #[test]
+    fn parse_err() {
+        assert_eq!(
+            parse_duration("1h30x"),
+            Err("bad unit \"x\" at 5 in \"1h30x\"".into())
+        );
+        assert_eq!(
+            parse_duration("1h 30"),
+            Err("missing unit after \"30\" at 6 in \"1h 30\"".into())
+        );
+        assert_eq!(
+            parse_duration("30m1h"),
+            Err("unit out of order \"h\" at 5 in \"30m1h\"".into())
+        );
+        assert_eq!(
+            parse_duration("1y999999999999999d"),
+            Err("overflow \"999999999999999d\" at 3 in \"1y999999999999999d\"".into())
+        );
+        assert_eq!(
+            parse_duration("99999999999999999999d"),
+            Err("overflow \"99999999999999999999\" at 1 in \"99999999999999999999d\"".into())
+        );
+        assert!(parse_duration("").is_err());
+        assert_eq!(parse_nonzero("0m"), Err("zero time \"0m\"".into()));
+        assert!(parse_duration("h").is_err());
+        assert!(parse_duration("_1m").is_err());
+        assert!(parse_duration("1m1m").is_err());
+        assert!(parse_duration("5hours").is_err());
+    }
+}
@@ -1,25 +1,44 @@
 //! 配置文件读取和处理
 use std::{
     fs::{read_dir, read_to_string},
+    io,
     path::{Path, PathBuf},
-    str::FromStr,
 };
 
-use log::{debug, error, warn};
+use log::{debug, warn};
 use regex::Regex;
 use serde::{Deserialize, Serialize};
 
+use crate::{
+    error::{PmbsError, PmbsResult},
+    snapshot::{DIR_PMBS, Trigger},
+};
+
 mod config_env;
+mod duration;
+mod mount;
 
 pub use config_env::{ConfigEnv, get_env_config};
+pub use duration::{parse_duration, parse_nonzero};
+pub use mount::same_mount;
 
 // *.toml
-const CONFIG_FILE_TOML: &'static str = ".toml";
+const CONFIG_FILE_TOML: &str = ".toml";
 // pmbs = 1
 const CONFIG_FILE_VERSION: u32 = 1;
+// skip_unchanged 默认强制快照间隔: 1 小时
+const DEFAULT_HEARTBEAT: &str = "1h";
+// 执行 hook 命令的默认超时时间: 1 分钟
+const DEFAULT_HOOK_TIMEOUT: &str = "1m";
+// 等待锁的超时时间
+const DEFAULT_LOCK_TIMEOUT: &str = "1m";
+// 自动清理: 最新快照之前 5 分钟的快照, 全部保留
+const DEFAULT_KEEP_LATEST: &str = "5m";
+// 自动清理: 容忍系统时间误差 10 秒
+const DEFAULT_KEEP_TOLERANCE: u64 = 10;
 
 /// pmbs 配置文件内容
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct PmbsConfig {
     /// 配置文件版本
     pub pmbs: u32,
@@ -27,15 +46,163 @@ pub struct PmbsConfig {
     pub subvol: String,
     /// 快照保留规则
     pub keep: Vec<PmbsConfigKeep>,
+    /// 自动清理: 最新快照之前这段时间内的快照, 全部保留 (默认 5m)
+    #[serde(default)]
+    pub keep_latest: Option<String>,
+    /// 自动清理: 容忍系统时间误差 (秒, 默认 10), 检查间隔时间时减去这个值
+    #[serde(default)]
+    pub keep_tolerance: Option<u64>,
+    /// 全局限制 (可选): 比最新的快照早这么久的快照, 不管保留规则, 全部清理
+    #[serde(default)]
+    pub max_age: Option<String>,
+    /// 全局限制 (可选): 快照总数不少于这个值 (即使保留规则要清理)
+    #[serde(default)]
+    pub min_keep: Option<u32>,
+    /// 全局限制 (可选): 快照总数不超过这个值
+    #[serde(default)]
+    pub max_total: Option<u32>,
+    /// 每次清理最多删除的快照个数 (可选), 其余的留到下次清理
+    #[serde(default)]
+    pub max_delete_per_run: Option<u32>,
+    /// 分批删除快照, 每批的个数 (可选)
+    #[serde(default)]
+    pub delete_batch_size: Option<u32>,
+    /// 每批删除之后, 等待删除的快照释放存储空间 (`btrfs subvol sync`)
+    #[serde(default)]
+    pub delete_sync: bool,
+    /// 删除快照之前不检查 parent UUID (快照仍然必须是只读的 subvol)
+    ///
+    /// 用于回滚 (用快照替换 subvol) 之后, 清理没有记录 UUID 的旧快照
+    #[serde(default)]
+    pub skip_parent_check: bool,
+
+    /// 快照保存目录 (可选), 默认为 subvol/.pmbs
+    ///
+    /// 必须和 subvol 在同一个 btrfs 文件系统, 并且路径中含有 `.pmbs` 开头的目录,
+    /// 比如 `/.pmbs-store/home`
+    #[serde(default)]
+    pub snapshot_dir: Option<String>,
+
+    /// 创建快照的间隔时间 (可选), 比如 `15m`
+    ///
+    /// `pmbs config snapshot` 距离 latest 快照不到这个时间时, 跳过快照
+    #[serde(default)]
+    pub interval: Option<String>,
+
+    /// 剩余存储空间检查 (可选)
+    #[serde(default)]
+    pub space: Option<PmbsConfigSpace>,
+    /// 剩余存储空间预算 (可选): 自动清理时, 剩余空间不足则继续清理快照
+    #[serde(default)]
+    pub space_budget: Option<PmbsConfigSpaceBudget>,
+
+    /// subvol 没有变化时, 跳过快照
+    #[serde(default)]
+    pub skip_unchanged: bool,
+    /// 跳过快照时, 强制快照的间隔时间 (默认 1h)
+    #[serde(default)]
+    pub heartbeat: Option<String>,
+
+    /// 创建快照之前执行的命令 (比如暂停数据库写入)
+    #[serde(default)]
+    pub pre_snapshot: Vec<Vec<String>>,
+    /// 创建快照之后执行的命令 (比如恢复数据库写入)
+    #[serde(default)]
+    pub post_snapshot: Vec<Vec<String>>,
+    /// 创建快照失败时执行的命令
+    #[serde(default)]
+    pub on_failure: Vec<Vec<String>>,
+    /// 执行每条命令的超时时间 (默认 1m)
+    #[serde(default)]
+    pub hook_timeout: Option<String>,
+    /// pre_snapshot 命令失败时的处理方式
+    #[serde(default)]
+    pub pre_snapshot_fail: HookFailPolicy,
+
+    /// 等待锁 (/.pmbs/.lock) 的超时时间 (默认 1m)
+    #[serde(default)]
+    pub lock_timeout: Option<String>,
+
+    /// (内部实现) 创建快照的间隔时间 (秒), 0 表示每次都创建
+    #[serde(skip)]
+    pub interval_s: u64,
+    /// (内部实现) 强制快照的间隔时间 (秒)
+    #[serde(skip)]
+    pub heartbeat_s: u64,
+    /// (内部实现) 执行每条命令的超时时间 (秒)
+    #[serde(skip)]
+    pub hook_timeout_s: u64,
+    /// (内部实现) 等待锁的超时时间 (秒)
+    #[serde(skip)]
+    pub lock_timeout_s: u64,
+    /// (内部实现) 全部保留最新快照之前的时间 (秒)
+    #[serde(skip)]
+    pub keep_latest_s: u64,
+    /// (内部实现) 容忍系统时间误差 (秒)
+    #[serde(skip)]
+    pub keep_tolerance_s: u64,
+    /// (内部实现) 快照的最大年龄 (秒), 0 表示不限制
+    #[serde(skip)]
+    pub max_age_s: u64,
+}
+
+impl PmbsConfig {
+    /// 没有配置文件时 (比如手动创建快照) 使用的默认配置
+    pub fn new_subvol(subvol: &str) -> Self {
+        Self {
+            pmbs: CONFIG_FILE_VERSION,
+            subvol: subvol.into(),
+            lock_timeout_s: parse_time(DEFAULT_LOCK_TIMEOUT).unwrap(),
+            hook_timeout_s: parse_time(DEFAULT_HOOK_TIMEOUT).unwrap(),
+            keep_latest_s: parse_time(DEFAULT_KEEP_LATEST).unwrap(),
+            keep_tolerance_s: DEFAULT_KEEP_TOLERANCE,
+            ..Default::default()
+        }
+    }
+
+    /// 快照保存目录, 比如 /home/.pmbs
+    pub fn dir_pmbs(&self) -> PathBuf {
+        match &self.snapshot_dir {
+            Some(d) => PathBuf::from(d),
+            None => {
+                let mut p = PathBuf::from(&self.subvol);
+                p.push(DIR_PMBS);
+                p
+            }
+        }
+    }
+}
+
+/// pre_snapshot 命令失败时的处理方式
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum HookFailPolicy {
+    /// 不创建快照 (默认)
+    #[default]
+    Abort,
+    /// 仍然创建快照
+    Continue,
 }
 
 /// 快照保留规则
+///
+/// 两种规则: 间隔时间 (`time`), 或者日历时间段 (`calendar`), 只能设置其中一个.
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct PmbsConfigKeep {
-    /// 间隔时间, 接受后缀 m (分钟), h (小时), d (天)
+    /// 间隔时间, 单位 s, m, h, d, w, mo (30 天), y (365 天), 可以组合, 比如 `1h30m`
+    #[serde(default)]
     pub time: String,
     /// 保留快照个数
     pub n: u32,
+    /// 规则适用的快照类别 (触发方式): timer (默认), manual, hook
+    #[serde(default)]
+    pub class: Option<Trigger>,
+    /// 日历时间段 (本地时间): 每个时间段保留一个快照, 保留最近 n 个时间段
+    #[serde(default)]
+    pub calendar: Option<Calendar>,
+    /// 日历规则: 保留时间段内的第一个, 还是最后一个快照
+    #[serde(default)]
+    pub pick: Pick,
 
     /// (内部实现) 间隔时间 (秒)
     #[serde(skip)]
@@ -48,13 +215,116 @@ impl PmbsConfigKeep {
         Self {
             time: "".into(),
             n,
+            class: None,
+            calendar: None,
+            pick: Pick::default(),
             s,
         }
     }
+
+    /// 用于调试: 日历规则
+    pub fn new_calendar(calendar: Calendar, n: u32, pick: Pick) -> Self {
+        Self {
+            calendar: Some(calendar),
+            pick,
+            ..Self::new_sn(0, n)
+        }
+    }
+
+    /// 规则适用的快照类别
+    pub fn get_class(&self) -> Trigger {
+        self.class.unwrap_or_default()
+    }
+}
+
+/// 日历时间段
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Calendar {
+    /// 每小时
+    Hourly,
+    /// 每天
+    Daily,
+    /// 每周 (ISO 8601, 周一开始)
+    Weekly,
+    /// 每月
+    Monthly,
+    /// 每年
+    Yearly,
+}
+
+impl Calendar {
+    /// 配置文件中的名称
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Hourly => "hourly",
+            Self::Daily => "daily",
+            Self::Weekly => "weekly",
+            Self::Monthly => "monthly",
+            Self::Yearly => "yearly",
+        }
+    }
+}
+
+/// 日历规则: 保留时间段内的哪一个快照
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Pick {
+    /// 第一个 (最旧的)
+    First,
+    /// 最后一个 (最新的, 默认)
+    #[default]
+    Last,
+}
+
+/// 剩余存储空间检查
+///
+/// 在创建快照之前检查 subvol 所在文件系统的剩余存储空间 (statvfs).
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PmbsConfigSpace {
+    /// 最少剩余字节数
+    #[serde(default)]
+    pub min_free_bytes: Option<u64>,
+    /// 最少剩余百分比 (0 ~ 100)
+    #[serde(default)]
+    pub min_free_percent: Option<u32>,
+    /// 剩余空间不足时的处理方式
+    #[serde(default)]
+    pub policy: SpacePolicy,
+}
+
+/// 剩余存储空间预算
+///
+/// `pmbs config clean` 按照保留规则清理之后, 如果剩余空间仍然低于限制,
+/// 继续删除价值最低的快照 (快照最密集的年龄分段中, 最旧的快照),
+/// 直到剩余空间恢复, 或者只剩下 `floor` 个快照.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PmbsConfigSpaceBudget {
+    /// 最少剩余字节数
+    #[serde(default)]
+    pub min_free_bytes: Option<u64>,
+    /// 最少剩余百分比 (0 ~ 100)
+    #[serde(default)]
+    pub min_free_percent: Option<u32>,
+    /// 最新的 floor 个快照 (不含固定的快照) 永远不会因为空间预算而删除
+    pub floor: u32,
+}
+
+/// 剩余空间不足时的处理方式
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SpacePolicy {
+    /// 只输出警告, 仍然创建快照 (默认)
+    #[default]
+    Warn,
+    /// 跳过本次快照
+    Skip,
+    /// 先执行一次紧急清理, 然后创建快照
+    Clean,
 }
 
 /// *.toml 配置文件 (含文件名)
-#[derive(Debug, Clone, Serialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct PmbsConfigFile {
     /// 文件名
     pub path: String,
@@ -63,133 +333,304 @@ pub struct PmbsConfigFile {
 }
 
 /// 列出配置文件
-pub fn list_config(config: &ConfigEnv) -> Vec<PathBuf> {
+pub fn list_config(config: &ConfigEnv) -> PmbsResult<Vec<PathBuf>> {
     let p = PathBuf::from(&config.dir_etc);
-    if p.is_dir() {
-        read_dir(p)
-            .unwrap()
-            .filter_map(|i| {
-                let f = i.unwrap();
-                // 检查名称 *.toml
-                let name = f.file_name().to_string_lossy().to_string();
-                if name.ends_with(CONFIG_FILE_TOML) {
-                    let p = f.path();
-                    if p.is_file() {
-                        // 检查通过
-                        Some(PathBuf::from(p))
-                    } else {
-                        // 不是普通文件, 忽略
-                        warn!("not regular file  {}", p.to_string_lossy());
-                        None
-                    }
-                } else {
-                    // 忽略
-                    None
-                }
-            })
-            .collect()
-    } else {
+    if !p.is_dir() {
         warn!("config dir not exist  {}", &config.dir_etc);
-        Vec::new()
+        return Ok(Vec::new());
     }
-}
-
-/// 读取配置文件 toml
-fn read_config_toml(path: &Path) -> Option<PmbsConfigFile> {
-    match read_to_string(path) {
-        Ok(s) => match toml::from_str::<PmbsConfig>(&s) {
-            Ok(config) => Some(PmbsConfigFile {
-                path: path.file_name().unwrap().to_string_lossy().to_string(),
-                config,
-            }),
-            Err(e) => {
-                error!("can not parse toml  {:?}", e);
-                None
+    let mut o = Vec::new();
+    for i in read_dir(&p).map_err(PmbsError::io(&p))? {
+        let f = i.map_err(PmbsError::io(&p))?;
+        // 检查名称 *.toml
+        let name = f.file_name().to_string_lossy().to_string();
+        if name.ends_with(CONFIG_FILE_TOML) {
+            let p = f.path();
+            if p.is_file() {
+                // 检查通过
+                o.push(p);
+            } else {
+                // 不是普通文件, 忽略
+                warn!("not regular file  {}", p.to_string_lossy());
             }
-        },
-        Err(e) => {
-            error!("can not read file  {:?}", e);
-            None
         }
     }
+    Ok(o)
 }
 
-/// 检查配置文件 keep.time 输入
-pub fn get_re_keep_time() -> Regex {
-    Regex::new(r"^[1-9][0-9_]*[mhd]$").unwrap()
+/// 读取配置文件 toml
+fn read_config_toml(path: &Path) -> PmbsResult<PmbsConfigFile> {
+    let s = read_to_string(path).map_err(PmbsError::io(path))?;
+    let config = toml::from_str::<PmbsConfig>(&s).map_err(|e| {
+        // toml 格式错误 (serde)
+        PmbsError::Config(format!("{}  {}", path.to_string_lossy(), e))
+    })?;
+    Ok(PmbsConfigFile {
+        path: path
+            .file_name()
+            .unwrap_or_default()
+            .to_string_lossy()
+            .to_string(),
+        config,
+    })
 }
 
-/// 解析 time 字符串, 转换为秒
-fn time_to_s(time: &str) -> u64 {
-    let mut time = time.to_string();
-    // 最后一个字符 (单位)
-    // 之间已经通过了正则表达式的检查, 此处的字符串已经是纯 ASCII, 可以不考虑多字节 utf-8
-    let unit = time.split_off(time.len() - 1);
-
-    let time: u64 = FromStr::from_str(&time).unwrap();
-    let unit: u64 = match unit.as_str() {
-        // 分钟 = 60 秒
-        "m" => 60,
-        // 小时 = 3600 秒
-        "h" => 3600,
-        // 天 = 86400 秒
-        "d" => 86400,
-
-        _ => unreachable!(),
+/// 检查并解析 time 字符串 (比如 `5m`, `1h30m`), 转换为秒
+pub fn parse_time(time: &str) -> Option<u64> {
+    parse_nonzero(time).ok()
+}
+
+/// 解析配置文件中的时间长度, 错误信息含有配置项名称
+fn check_time(name: &str, time: &str) -> Result<u64, String> {
+    parse_nonzero(time).map_err(|e| format!("bad {} = {}  {}", name, time, e))
+}
+
+/// 检查快照保存目录 snapshot_dir
+pub fn get_re_snapshot_dir() -> Regex {
+    // 路径中必须含有 `.pmbs` 开头的目录, 比如 `/.pmbs-store/home`
+    Regex::new(r"^/(.+/)?\.pmbs(-[^/]+)?(/[^/.][^/]*)*$").unwrap()
+}
+
+fn check_snapshot_dir(subvol: &str, d: &str) -> Result<(), String> {
+    if !get_re_snapshot_dir().is_match(d) {
+        return Err(format!("bad snapshot_dir = {}", d));
+    }
+    // 不能在 subvol 之内 (除了默认的 subvol/.pmbs)
+    let p = Path::new(d);
+    if p.starts_with(subvol) && !p.starts_with(Path::new(subvol).join(DIR_PMBS)) {
+        return Err(format!("snapshot_dir inside subvol  {}", d));
+    }
+    // 必须在同一个文件系统 (btrfs 只能在同一个文件系统中移动快照)
+    if mount::same_fs(Path::new(subvol), p) == Some(false) {
+        return Err(format!(
+            "snapshot_dir not on the same filesystem as subvol  {}",
+            d
+        ));
+    }
+    if !p.is_dir() {
+        warn!("snapshot_dir not exist  {}", d);
+    }
+    Ok(())
+}
+
+/// 读取所有配置文件, 配置文件错误时返回错误.
+///
+/// 只读的命令 (`read_only`, 比如 `pmbs ls`) 可以由普通用户执行,
+/// 此时忽略没有权限读取的配置文件 (EACCES).
+pub fn read_all_config(c: &ConfigEnv, read_only: bool) -> PmbsResult<Vec<PmbsConfigFile>> {
+    let denied = |e: &PmbsError| {
+        read_only && matches!(e, PmbsError::Io(_, e) if e.kind() == io::ErrorKind::PermissionDenied)
+    };
+    let list = match list_config(c) {
+        Ok(list) => list,
+        Err(e) if denied(&e) => {
+            warn!("{}", e);
+            return Ok(Vec::new());
+        }
+        Err(e) => return Err(e),
     };
+    let mut o = Vec::new();
+    for i in list {
+        match read_config(&i) {
+            Ok(f) => o.push(f),
+            Err(e) if denied(&e) => warn!("{}", e),
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(o)
+}
 
-    // 计算时间
-    time * unit
+/// 读取所有配置文件, 查找指定 subvol 的配置.
+///
+/// 如果没有找到, 返回默认配置.
+pub fn find_config(c: &ConfigEnv, subvol: &str, read_only: bool) -> PmbsResult<PmbsConfig> {
+    let same = |a: &str| {
+        Path::new(a) == Path::new(subvol)
+            || match (
+                Path::new(a).canonicalize(),
+                Path::new(subvol).canonicalize(),
+            ) {
+                (Ok(a), Ok(b)) => a == b,
+                _ => false,
+            }
+    };
+    for f in read_all_config(c, read_only)? {
+        if same(&f.config.subvol) {
+            debug!("found config {}  {}", f.path, subvol);
+            return Ok(f.config);
+        }
+    }
+    Ok(PmbsConfig::new_subvol(subvol))
 }
 
 /// 检查配置文件, 并解析 time 字符串
-fn check_config(c: &mut PmbsConfig) -> bool {
+fn check_config(c: &mut PmbsConfig) -> Result<(), String> {
     // 配置文件版本
     if c.pmbs != CONFIG_FILE_VERSION {
-        error!("bad config file version  {}", c.pmbs);
-        return false;
+        return Err(format!("bad config file version  {}", c.pmbs));
     }
     // subvol 路径
-    if c.subvol.trim().len() < 1 {
-        error!("empty subvol path");
-        return false;
+    if c.subvol.trim().is_empty() {
+        return Err("empty subvol path".into());
     }
     let p = PathBuf::from(&c.subvol);
     if !p.is_dir() {
         warn!("subvol not exist  {}", c.subvol);
     }
 
-    // 初始化正则表达式 (避免在循环内)
-    let re_time = get_re_keep_time();
+    // 快照保存目录
+    if let Some(d) = &c.snapshot_dir {
+        check_snapshot_dir(&c.subvol, d)?;
+    }
 
     // 快照保留规则 (基本检查)
     for i in &mut c.keep {
         // n 不可为 0
         if i.n < 1 {
-            error!("bad n = {}", i.n);
-            return false;
+            return Err(format!("bad n = {}", i.n));
         }
 
-        // 检查时间格式
-        if !re_time.is_match(&i.time) {
-            error!("bad time = {}", i.time);
-            return false;
+        // 日历规则
+        if i.calendar.is_some() {
+            if !i.time.is_empty() {
+                return Err(format!("both time and calendar  {}", i.time));
+            }
+            continue;
         }
-        i.s = time_to_s(&i.time);
+        // 检查时间格式
+        i.s = check_time("time", &i.time)?;
 
         debug!("time {} = {}s", i.time, i.s);
     }
 
+    // 创建快照的间隔时间
+    if let Some(interval) = &c.interval {
+        c.interval_s = check_time("interval", interval)?;
+        debug!("interval {} = {}s", interval, c.interval_s);
+    }
+
+    // 跳过没有变化的快照
+    let heartbeat = c.heartbeat.as_deref().unwrap_or(DEFAULT_HEARTBEAT);
+    c.heartbeat_s = check_time("heartbeat", heartbeat)?;
+    if c.skip_unchanged {
+        // 快照保存在 subvol 之中时, pmbs 自己写入的 .lock, meta, latest
+        // 也会使 subvol 的 generation 增加, 永远不会跳过
+        if c.dir_pmbs() == Path::new(&c.subvol).join(DIR_PMBS) {
+            return Err(format!(
+                "skip_unchanged requires snapshot_dir outside subvol  {}",
+                c.subvol
+            ));
+        }
+        debug!(
+            "skip_unchanged, heartbeat {} = {}s",
+            heartbeat, c.heartbeat_s
+        );
+    }
+
+    // 创建快照前后执行的命令
+    for i in c
+        .pre_snapshot
+        .iter()
+        .chain(c.post_snapshot.iter())
+        .chain(c.on_failure.iter())
+    {
+        if i.is_empty() || i[0].trim().is_empty() {
+            return Err(format!("empty hook command  {:?}", i));
+        }
+    }
+    let hook_timeout = c.hook_timeout.as_deref().unwrap_or(DEFAULT_HOOK_TIMEOUT);
+    c.hook_timeout_s = check_time("hook_timeout", hook_timeout)?;
+
+    // 等待锁的超时时间
+    let lock_timeout = c.lock_timeout.as_deref().unwrap_or(DEFAULT_LOCK_TIMEOUT);
+    c.lock_timeout_s = check_time("lock_timeout", lock_timeout)?;
+
+    // 自动清理: 全部保留的时间, 容忍时间误差
+    let keep_latest = c.keep_latest.as_deref().unwrap_or(DEFAULT_KEEP_LATEST);
+    c.keep_latest_s = check_time("keep_latest", keep_latest)?;
+    c.keep_tolerance_s = c.keep_tolerance.unwrap_or(DEFAULT_KEEP_TOLERANCE);
+    // 容忍误差必须小于每条规则的间隔时间, 否则规则失效
+    if let Some(r) = c
+        .keep
+        .iter()
+        .find(|x| x.calendar.is_none() && x.s <= c.keep_tolerance_s)
+    {
+        return Err(format!(
+            "keep_tolerance = {} too big for time = {}",
+            c.keep_tolerance_s, r.time
+        ));
+    }
+    if c.keep_tolerance_s >= c.keep_latest_s {
+        return Err(format!(
+            "keep_tolerance = {} too big for keep_latest = {}",
+            c.keep_tolerance_s, keep_latest
+        ));
+    }
+
+    // 全局限制
+    if let Some(max_age) = &c.max_age {
+        c.max_age_s = check_time("max_age", max_age)?;
+        if c.max_age_s <= c.keep_latest_s {
+            warn!("max_age = {} is shorter than keep_latest !", max_age);
+        }
+    }
+    // 删除快照的限制
+    if c.max_delete_per_run == Some(0) {
+        return Err("bad max_delete_per_run = 0".into());
+    }
+    if c.delete_batch_size == Some(0) {
+        return Err("bad delete_batch_size = 0".into());
+    }
+    if let Some(max_total) = c.max_total {
+        if max_total < 1 {
+            return Err(format!("bad max_total = {}", max_total));
+        }
+        if let Some(min_keep) = c.min_keep
+            && min_keep > max_total
+        {
+            return Err(format!(
+                "min_keep = {} > max_total = {}",
+                min_keep, max_total
+            ));
+        }
+    }
+
+    // 剩余存储空间检查
+    if let Some(space) = &c.space {
+        if space.min_free_bytes.is_none() && space.min_free_percent.is_none() {
+            warn!("empty space rule !");
+        }
+        if let Some(percent) = space.min_free_percent
+            && percent > 100
+        {
+            return Err(format!("bad space.min_free_percent = {}", percent));
+        }
+    }
+    // 剩余存储空间预算
+    if let Some(b) = &c.space_budget {
+        if b.min_free_bytes.is_none() && b.min_free_percent.is_none() {
+            warn!("empty space_budget rule !");
+        }
+        if let Some(percent) = b.min_free_percent
+            && percent > 100
+        {
+            return Err(format!("bad space_budget.min_free_percent = {}", percent));
+        }
+        // 至少保留最新的快照
+        if b.floor < 1 {
+            return Err(format!("bad space_budget.floor = {}", b.floor));
+        }
+    }
+
     // 更多对快照保留规则的检查 (警告)
-    if c.keep.len() < 1 {
+    if c.keep.is_empty() {
         // 没有配置保留规则
         warn!("empty keep rule !");
     }
     // 快照保留的总数
     let mut sum_n: u32 = 0;
-    // 上一条规则的间隔时间
-    let mut last_time: Option<String> = None;
-    let mut last_s = 0;
+    // 同一类别的上一条规则的间隔时间
+    let mut last: Vec<(Trigger, String, u64)> = Vec::new();
     for i in &c.keep {
         // 保留快照太多
         if i.n > 200 {
@@ -199,18 +640,24 @@ fn check_config(c: &mut PmbsConfig) -> bool {
         if i.s > (31 * 86400) {
             warn!("too big time = {} !", i.time);
         }
+        // 日历规则没有间隔时间
+        if i.calendar.is_some() {
+            sum_n += i.n;
+            continue;
+        }
         // 上一条规则的时间, 必须比下一条短
-        if let Some(time) = last_time {
-            if i.s <= last_s {
-                warn!("next rule time is shorter !  {} <= {}", i.time, time);
-            }
+        let class = i.get_class();
+        if let Some((_, time, last_s)) = last.iter().find(|x| x.0 == class)
+            && i.s <= *last_s
+        {
+            warn!("next rule time is shorter !  {} <= {}", i.time, time);
         }
 
         // 计算总数
         sum_n += i.n;
         // 更新上一条数据
-        last_time = Some(i.time.clone());
-        last_s = i.s;
+        last.retain(|x| x.0 != class);
+        last.push((class, i.time.clone(), i.s));
     }
     // 保留了太多快照
     debug!("sum_n = {}", sum_n);
@@ -218,93 +665,207 @@ fn check_config(c: &mut PmbsConfig) -> bool {
         warn!("too many rules !  {}", sum_n);
     }
 
-    true
+    Ok(())
 }
 
 /// 读取配置文件并检查
-pub fn read_config(path: &Path) -> Option<PmbsConfigFile> {
+pub fn read_config(path: &Path) -> PmbsResult<PmbsConfigFile> {
     debug!("read config  {}", path.to_string_lossy());
 
-    match read_config_toml(path) {
-        Some(mut c) => {
-            if check_config(&mut c.config) {
-                // 检查通过
-                Some(c)
-            } else {
-                // 配置文件内容错误
-                error!("bad config file  {}", path.to_string_lossy());
-                None
-            }
-        }
-        None => {
-            // toml 格式错误 (serde)
-            error!("bad config toml file  {}", path.to_string_lossy());
-            None
-        }
-    }
+    let mut c = read_config_toml(path)?;
+    // 配置文件内容错误
+    check_config(&mut c.config)
+        .map_err(|e| PmbsError::Config(format!("{}  {}", path.to_string_lossy(), e)))?;
+    Ok(c)
 }
 
 #[cfg(test)]
 mod test {
+    use std::fs::{create_dir_all, write};
+
     use super::*;
+    use crate::test_util::TmpDir;
 
     /// 解析配置文件中的 time 字符串
     #[test]
     fn parse_time() {
-        assert_eq!(time_to_s("1m"), 60);
-        assert_eq!(time_to_s("5m"), 300);
-        assert_eq!(time_to_s("20m"), 1200);
-        assert_eq!(time_to_s("1h"), 3600);
-        assert_eq!(time_to_s("2h"), 7200);
-        assert_eq!(time_to_s("1d"), 8_6400);
-        assert_eq!(time_to_s("7d"), 60_4800);
-        assert_eq!(time_to_s("28d"), 241_9200);
+        assert_eq!(super::parse_time("1m"), Some(60));
+        assert_eq!(super::parse_time("5m"), Some(300));
+        assert_eq!(super::parse_time("20m"), Some(1200));
+        assert_eq!(super::parse_time("1h"), Some(3600));
+        assert_eq!(super::parse_time("2h"), Some(7200));
+        assert_eq!(super::parse_time("1d"), Some(8_6400));
+        assert_eq!(super::parse_time("7d"), Some(60_4800));
+        assert_eq!(super::parse_time("28d"), Some(241_9200));
+        assert_eq!(super::parse_time("1_000m"), Some(6_0000));
+        // 溢出
+        assert_eq!(super::parse_time("99999999999999999999d"), None);
+    }
+
+    /// keep_latest, keep_tolerance: 默认值, 以及非默认值的检查
+    #[test]
+    fn check_keep_latest() {
+        let mut c = PmbsConfig::new_subvol("/home");
+        c.keep = vec![PmbsConfigKeep::new_sn(0, 10)];
+        c.keep[0].time = "1m".into();
+        check_config(&mut c).unwrap();
+        assert_eq!((c.keep_latest_s, c.keep_tolerance_s), (300, 10));
+
+        c.keep_latest = Some("30m".into());
+        c.keep_tolerance = Some(30);
+        check_config(&mut c).unwrap();
+        assert_eq!((c.keep_latest_s, c.keep_tolerance_s), (1800, 30));
+
+        // 容忍误差不能大于规则的间隔时间
+        c.keep_tolerance = Some(60);
+        assert!(check_config(&mut c).is_err());
+        c.keep_tolerance = None;
+        c.keep_latest = Some("0m".into());
+        assert!(check_config(&mut c).is_err());
+    }
+
+    /// skip_unchanged: 快照必须保存在 subvol 之外
+    #[test]
+    fn check_skip_unchanged() {
+        let mut c = PmbsConfig::new_subvol("/home");
+        c.skip_unchanged = true;
+        assert!(check_config(&mut c).is_err());
+        c.snapshot_dir = Some("/home/.pmbs".into());
+        assert!(check_config(&mut c).is_err());
+        c.snapshot_dir = Some("/.pmbs-store/home".into());
+        check_config(&mut c).unwrap();
+        assert_eq!(c.heartbeat_s, 3600);
+    }
+
+    /// 全局限制: max_age, min_keep, max_total
+    #[test]
+    fn check_guard() {
+        let mut c = PmbsConfig::new_subvol("/home");
+        c.max_age = Some("90d".into());
+        c.min_keep = Some(10);
+        c.max_total = Some(500);
+        check_config(&mut c).unwrap();
+        assert_eq!(c.max_age_s, 90 * 86400);
+
+        c.max_total = Some(5);
+        assert!(check_config(&mut c).is_err());
+        c.max_total = Some(0);
+        c.min_keep = None;
+        assert!(check_config(&mut c).is_err());
+        c.max_total = None;
+        c.max_age = Some("90".into());
+        assert!(check_config(&mut c).is_err());
+    }
+
+    /// find_config: 配置文件错误时返回错误, 而不是使用默认配置
+    #[test]
+    fn find_config_error() {
+        let tmp = TmpDir::new("find-config");
+        let c = tmp.env();
+        create_dir_all(&c.dir_etc).unwrap();
+        let subvol = tmp.join("home").to_string_lossy().to_string();
+        let etc = Path::new(&c.dir_etc);
+        write(
+            etc.join("home.toml"),
+            format!(
+                "pmbs = 1\nsubvol = \"{}\"\nsnapshot_dir = \"{}\"\nkeep = []\n",
+                subvol,
+                tmp.join(".pmbs-store/home").to_string_lossy()
+            ),
+        )
+        .unwrap();
+        let config = find_config(&c, &subvol, false).unwrap();
+        assert!(config.snapshot_dir.is_some());
+        assert!(
+            find_config(&c, "/srv", true)
+                .unwrap()
+                .snapshot_dir
+                .is_none()
+        );
+
+        write(etc.join("bad.toml"), "pmbs = 1\nsubvol = [").unwrap();
+        assert!(matches!(
+            find_config(&c, &subvol, false),
+            Err(PmbsError::Config(_))
+        ));
+        assert!(matches!(
+            find_config(&c, &subvol, true),
+            Err(PmbsError::Config(_))
+        ));
     }
 }
 
 /// 对正则表达式匹配进行测试
 #[cfg(test)]
+#[allow(clippy::bool_assert_comparison)]
 mod test_re {
     use super::*;
 
     #[test]
     fn re_keep_time_should_match() {
-        let re = get_re_keep_time();
-
-        assert_eq!(re.is_match("1m"), true);
-        assert_eq!(re.is_match("5m"), true);
-        assert_eq!(re.is_match("20m"), true);
-        assert_eq!(re.is_match("1h"), true);
-        assert_eq!(re.is_match("1d"), true);
-
-        assert_eq!(re.is_match("7d"), true);
-        assert_eq!(re.is_match("28d"), true);
-        assert_eq!(re.is_match("30d"), true);
-        assert_eq!(re.is_match("365d"), true);
-        assert_eq!(re.is_match("2000d"), true);
+        let re = |x: &str| parse_time(x).is_some();
+
+        assert_eq!(re("1m"), true);
+        assert_eq!(re("5m"), true);
+        assert_eq!(re("20m"), true);
+        assert_eq!(re("1h"), true);
+        assert_eq!(re("1d"), true);
+
+        assert_eq!(re("7d"), true);
+        assert_eq!(re("28d"), true);
+        assert_eq!(re("30d"), true);
+        assert_eq!(re("365d"), true);
+        assert_eq!(re("2000d"), true);
+        // 秒, 周, 月, 年, 组合
+        assert_eq!(re("90s"), true);
+        assert_eq!(re("2w"), true);
+        assert_eq!(re("1mo"), true);
+        assert_eq!(re("1y"), true);
+        assert_eq!(re("1h30m"), true);
     }
 
     #[test]
     fn re_keep_time_not_match() {
-        let re = get_re_keep_time();
+        let re = |x: &str| parse_time(x).is_some();
 
         // 空
-        assert_eq!(re.is_match(""), false);
+        assert_eq!(re(""), false);
         // 纯数字, 没有单位
-        assert_eq!(re.is_match("1"), false);
-        assert_eq!(re.is_match("234"), false);
+        assert_eq!(re("1"), false);
+        assert_eq!(re("234"), false);
         // 没有数字
-        assert_eq!(re.is_match("m"), false);
-        assert_eq!(re.is_match("h"), false);
-        assert_eq!(re.is_match("d"), false);
+        assert_eq!(re("m"), false);
+        assert_eq!(re("h"), false);
+        assert_eq!(re("d"), false);
         // 不支持的单位
-        assert_eq!(re.is_match("2w"), false);
-        assert_eq!(re.is_match("1y"), false);
-        assert_eq!(re.is_match("42min"), false);
-        assert_eq!(re.is_match("5hours"), false);
-        assert_eq!(re.is_match("5s"), false);
+        assert_eq!(re("42min"), false);
+        assert_eq!(re("5hours"), false);
+        assert_eq!(re("2x"), false);
+        // 单位顺序错误, 重复
+        assert_eq!(re("30m1h"), false);
+        assert_eq!(re("1m1m"), false);
         // 随意错误格式
-        assert_eq!(re.is_match("balabala"), false);
-        assert_eq!(re.is_match("x666"), false);
+        assert_eq!(re("balabala"), false);
+        assert_eq!(re("x666"), false);
+    }
+
+    #[test]
+    fn re_snapshot_dir() {
+        let re = get_re_snapshot_dir();
+
+        assert_eq!(re.is_match("/.pmbs"), true);
+        assert_eq!(re.is_match("/home/.pmbs"), true);
+        assert_eq!(re.is_match("/.pmbs-store/home"), true);
+        assert_eq!(re.is_match("/mnt/data/.pmbs-store/home/a"), true);
+
+        assert_eq!(re.is_match(""), false);
+        assert_eq!(re.is_match("/"), false);
+        assert_eq!(re.is_match(".pmbs"), false);
+        assert_eq!(re.is_match("/home"), false);
+        assert_eq!(re.is_match("/home/pmbs"), false);
+        assert_eq!(re.is_match("/.pmbs-store/"), false);
+        assert_eq!(re.is_match("/.pmbs-store//home"), false);
+        assert_eq!(re.is_match("/.pmbs-store/.home"), false);
+        assert_eq!(re.is_match("/.pmbsx/home"), false);
     }
 }
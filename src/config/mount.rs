@@ -0,0 +1,133 @@
+//! 检查两个路径是否在同一个文件系统 (`/proc/self/mountinfo`)
+//!
+//! btrfs 的每个 subvol 有单独的 `st_dev`, 所以不能直接比较 `stat`.
+//! 但是同一个 btrfs 文件系统的所有挂载 (不同的 subvol) 共享 superblock,
+//! mountinfo 中的设备号 (`major:minor`) 相同.
+//!
+//! 另外 `rename` 不能跨挂载点 (`EXDEV`), 即使是同一个文件系统,
+//! 所以移动快照时还需要检查是否在同一个挂载.
+use std::{
+    fs::read_to_string,
+    path::{Path, PathBuf},
+};
+
+/// 一个挂载点
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Mount {
+    /// 挂载点路径
+    point: PathBuf,
+    /// 文件系统的设备号, 比如 `0:35`
+    dev: String,
+}
+
+/// 还原 mountinfo 中转义的字符 (比如空格 `\040`)
+fn unescape(s: &str) -> String {
+    let b = s.as_bytes();
+    let mut o = Vec::with_capacity(b.len());
+    let mut i = 0;
+    while i < b.len() {
+        if b[i] == b'\\'
+            && i + 3 < b.len()
+            && let Some(c) = std::str::from_utf8(&b[i + 1..i + 4])
+                .ok()
+                .and_then(|x| u8::from_str_radix(x, 8).ok())
+        {
+            o.push(c);
+            i += 4;
+            continue;
+        }
+        o.push(b[i]);
+        i += 1;
+    }
+    String::from_utf8_lossy(&o).to_string()
+}
+
+/// 解析 mountinfo, 比如:
+///
+/// `36 35 0:35 /@home /home rw,relatime shared:1 - btrfs /dev/sda2 rw`
+fn parse_mountinfo(text: &str) -> Vec<Mount> {
+    text.lines()
+        .filter_map(|line| {
+            let f: Vec<&str> = line.split(' ').collect();
+            if f.len() < 5 {
+                return None;
+            }
+            Some(Mount {
+                point: PathBuf::from(unescape(f[4])),
+                dev: f[2].to_string(),
+            })
+        })
+        .collect()
+}
+
+/// 路径所在的挂载 (最长的挂载点前缀, 后面的挂载覆盖前面的)
+fn find_mount<'a>(list: &'a [Mount], p: &Path) -> Option<&'a Mount> {
+    let mut best: Option<&Mount> = None;
+    for i in list {
+        if p.starts_with(&i.point)
+            && best.is_none_or(|x| i.point.components().count() >= x.point.components().count())
+        {
+            best = Some(i);
+        }
+    }
+    best
+}
+
+/// 规范化路径: 不存在时, 使用存在的最长的上级目录
+fn real_path(p: &Path) -> Option<PathBuf> {
+    p.ancestors().find_map(|x| x.canonicalize().ok())
+}
+
+/// 两个路径所在的挂载, 无法判断时 (比如没有 /proc) 返回 None
+fn find_mount_pair(a: &Path, b: &Path) -> Option<(Mount, Mount)> {
+    let list = parse_mountinfo(&read_to_string("/proc/self/mountinfo").ok()?);
+    let a = find_mount(&list, &real_path(a)?)?.clone();
+    let b = find_mount(&list, &real_path(b)?)?.clone();
+    Some((a, b))
+}
+
+/// 两个路径是否在同一个文件系统, 无法判断时 (比如没有 /proc) 返回 None
+pub fn same_fs(a: &Path, b: &Path) -> Option<bool> {
+    let (a, b) = find_mount_pair(a, b)?;
+    Some(a.dev == b.dev)
+}
+
+/// 两个路径是否在同一个挂载 (可以 `rename`), 无法判断时返回 None
+pub fn same_mount(a: &Path, b: &Path) -> Option<bool> {
+    let (a, b) = find_mount_pair(a, b)?;
+    Some(a == b)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn mountinfo() {
+        let text = r"21 1 0:35 /@ / rw,relatime shared:1 - btrfs /dev/sda2 rw
+22 21 0:35 /@home /home rw,relatime shared:2 - btrfs /dev/sda2 rw
+23 21 8:1 / /boot rw,relatime shared:3 - ext4 /dev/sda1 rw
+24 22 0:40 / /home/my\040disk rw - tmpfs tmpfs rw
+";
+        let list = parse_mountinfo(text);
+        assert_eq!(list.len(), 4);
+        assert_eq!(list[3].point, PathBuf::from("/home/my disk"));
+        assert_eq!(unescape(r"/a\\134b\0"), r"/a\\b\0");
+        assert_eq!(unescape("/数据\\040x"), "/数据 x");
+
+        let dev = |p: &str| find_mount(&list, Path::new(p)).unwrap().dev.as_str();
+        // 同一个 btrfs 文件系统的不同 subvol
+        assert_eq!(dev("/home/alice"), "0:35");
+        assert_eq!(dev("/.pmbs-store/home"), "0:35");
+        assert_eq!(dev("/boot/efi"), "8:1");
+        assert_eq!(dev("/home/my disk/a"), "0:40");
+        assert_eq!(dev("/home/my diskx"), "0:35");
+        // 同一个文件系统, 但是不同的挂载: 不能 rename
+        let m = |p: &str| find_mount(&list, Path::new(p)).unwrap();
+        assert_ne!(m("/home/alice"), m("/.pmbs-store/home"));
+        assert_eq!(m("/home/alice"), m("/home/bob"));
+
+        assert!(same_fs(Path::new("/"), Path::new("/")).unwrap_or(true));
+        assert!(same_mount(Path::new("/"), Path::new("/")).unwrap_or(true));
+    }
+}
@@ -0,0 +1,100 @@
+//! 错误类型
+//!
+//! `pmbs` 命令的退出码 (见 [`PmbsError::exit_code`]):
+//!
+//! | 退出码 | 错误                  | 说明                                   |
+//! | :----- | :-------------------- | :------------------------------------- |
+//! | `0`    |                       | 成功                                   |
+//! | `1`    | [`PmbsError::Arg`]    | 命令行参数错误 (或者找不到指定的快照)  |
+//! | `2`    | [`PmbsError::Config`] | 配置文件错误                           |
+//! | `3`    | [`PmbsError::Io`]     | 文件读写错误                           |
+//! | `4`    | [`PmbsError::Command`] | 执行命令失败 (比如 `btrfs`)           |
+//! | `5`    | [`PmbsError::Signal`] | 执行的命令被信号终止                   |
+//! | `6`    | [`PmbsError::Safety`] | 安全检查失败, 拒绝删除                 |
+//! | `7`    | [`PmbsError::Lock`]   | 锁被占用 (等待超时)                    |
+//! | `8`    | [`PmbsError::Problem`] | 检查发现问题 (`pmbs fsck`)            |
+use std::{fmt, io, path::Path};
+
+/// pmbs 错误
+#[derive(Debug)]
+pub enum PmbsError {
+    /// 命令行参数错误
+    Arg(String),
+    /// 配置文件错误
+    Config(String),
+    /// 文件读写错误 (路径, 错误)
+    Io(String, io::Error),
+    /// 执行命令失败 (命令, 退出码)
+    Command(String, i32),
+    /// 执行的命令被信号终止 (命令, 信号)
+    Signal(String, i32),
+    /// 安全检查失败 (比如要删除的路径不符合格式)
+    Safety(String),
+    /// 锁被占用
+    Lock(String),
+    /// 检查发现问题 (比如 `pmbs fsck` 还有未修复的问题)
+    Problem(String),
+}
+
+/// pmbs 结果
+pub type PmbsResult<T> = Result<T, PmbsError>;
+
+impl PmbsError {
+    /// 用于 `map_err`: 文件读写错误
+    pub fn io<P: AsRef<Path>>(p: P) -> impl FnOnce(io::Error) -> Self {
+        move |e| Self::Io(p.as_ref().to_string_lossy().to_string(), e)
+    }
+
+    /// 命令的退出码
+    pub fn exit_code(&self) -> u8 {
+        match self {
+            Self::Arg(_) => 1,
+            Self::Config(_) => 2,
+            Self::Io(..) => 3,
+            Self::Command(..) => 4,
+            Self::Signal(..) => 5,
+            Self::Safety(_) => 6,
+            Self::Lock(_) => 7,
+            Self::Problem(_) => 8,
+        }
+    }
+}
+
+impl fmt::Display for PmbsError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Arg(m) => write!(f, "{}", m),
+            Self::Config(m) => write!(f, "bad config  {}", m),
+            Self::Io(p, e) => write!(f, "io error  {}  {}", p, e),
+            Self::Command(c, code) => write!(f, "command failed  {}  exit code {}", c, code),
+            Self::Signal(c, s) => write!(f, "command killed by signal {}  {}", s, c),
+            Self::Safety(m) => write!(f, "safety check failed  {}", m),
+            Self::Lock(m) => write!(f, "lock busy  {}", m),
+            Self::Problem(m) => write!(f, "{}", m),
+        }
+    }
+}
+
+impl std::error::Error for PmbsError {}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn exit_code() {
+        let list = [
+            PmbsError::Arg("".into()),
+            PmbsError::Config("".into()),
+            PmbsError::io("/")(io::Error::other("")),
+            PmbsError::Command("".into(), 1),
+            PmbsError::Signal("".into(), 9),
+            PmbsError::Safety("".into()),
+            PmbsError::Lock("".into()),
+            PmbsError::Problem("".into()),
+        ];
+        let mut code: Vec<u8> = list.iter().map(|x| x.exit_code()).collect();
+        code.dedup();
+        assert_eq!(code, vec![1, 2, 3, 4, 5, 6, 7, 8]);
+    }
+}
@@ -1,7 +1,12 @@
 #![doc = include_str!("../doc/pmbs.md")]
 #![deny(unsafe_code)]
 
+pub mod backend;
 pub mod clean;
 pub mod cli;
 pub mod config;
+pub mod error;
 pub mod snapshot;
+
+#[cfg(test)]
+mod test_util;
@@ -12,13 +12,13 @@ pm_init!();
 
 use pmbs::cli;
 
-fn main() -> Result<(), ExitCode> {
+fn main() -> ExitCode {
     init_env_logger();
 
     if let Some(a) = cli_arg(print_version) {
         cli::main(a)
     } else {
         // pm-bin 会处理 `--version` 和 `--版本`
-        Ok(())
+        ExitCode::SUCCESS
     }
 }
@@ -0,0 +1,352 @@
+//! 检查 (并修复) 快照保存目录 (比如 `/home/.pmbs`) 的一致性
+use std::{
+    fs::{read_dir, read_link, remove_dir},
+    io,
+    path::Path,
+    str::FromStr,
+};
+
+use log::info;
+
+use crate::{
+    backend::{BACKEND_DIR, get_backend},
+    clean::{get_re_t, get_re_year, read_pin, write_pin},
+    config::ConfigEnv,
+    error::{PmbsError, PmbsResult},
+};
+
+use super::{SYMLINK_LATEST, SafeDir, get_year, write_latest};
+
+/// 发现的问题
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Problem {
+    /// 有快照, 但是 latest 符号链接不存在
+    LatestMissing { expect: String },
+    /// latest 指向不存在的快照
+    LatestDangling { to: String, expect: Option<String> },
+    /// latest 指向的不是最新的快照
+    LatestNotNewest { to: String, expect: String },
+    /// latest 不是符号链接 (不修复)
+    LatestNotSymlink,
+    /// 残留的临时符号链接 `latest.T` (创建 latest 时中断)
+    LatestTmp { name: String },
+    /// 年目录和时间戳的年不一致, 比如 `2024/1756392923`
+    WrongYear { path: String, expect: String },
+    /// 不是 subvol (普通目录或者文件, 不修复)
+    NotSubvol { path: String },
+    /// 不是只读的 subvol (不修复)
+    NotReadonly { path: String },
+    /// 空的年目录
+    EmptyYear { year: String },
+}
+
+impl Problem {
+    /// 显示问题
+    pub fn describe(&self) -> String {
+        match self {
+            Self::LatestMissing { expect } => format!("latest missing  expect {}", expect),
+            Self::LatestDangling { to, expect } => format!(
+                "latest dangling  {}  expect {}",
+                to,
+                expect.as_deref().unwrap_or("-")
+            ),
+            Self::LatestNotNewest { to, expect } => {
+                format!("latest not newest  {}  expect {}", to, expect)
+            }
+            Self::LatestNotSymlink => "latest not symlink".into(),
+            Self::LatestTmp { name } => format!("leftover temp symlink  {}", name),
+            Self::WrongYear { path, expect } => format!("wrong year  {}  expect {}", path, expect),
+            Self::NotSubvol { path } => format!("not subvol  {}", path),
+            Self::NotReadonly { path } => format!("not readonly  {}", path),
+            Self::EmptyYear { year } => format!("empty year dir  {}", year),
+        }
+    }
+
+    /// 是否可以自动修复
+    pub fn can_repair(&self) -> bool {
+        !matches!(
+            self,
+            Self::LatestNotSymlink | Self::NotSubvol { .. } | Self::NotReadonly { .. }
+        )
+    }
+
+    /// 修复时需要先修复其它问题 (快照路径可能改变)
+    fn is_final(&self) -> bool {
+        matches!(
+            self,
+            Self::LatestMissing { .. }
+                | Self::LatestDangling { .. }
+                | Self::LatestNotNewest { .. }
+                | Self::EmptyYear { .. }
+        )
+    }
+}
+
+/// 检查快照保存目录
+pub fn fsck_check(c: &ConfigEnv, dir: &Path) -> PmbsResult<Vec<Problem>> {
+    let mut o = Vec::new();
+    if !dir.is_dir() {
+        return Ok(o);
+    }
+    let b = get_backend(c);
+    let re_year = get_re_year();
+    let re_t = get_re_t();
+    // 最新的快照: (时间戳, 路径)
+    let mut newest: Option<(u64, String)> = None;
+
+    for i in read_dir(dir).map_err(PmbsError::io(dir))? {
+        let d = i.map_err(PmbsError::io(dir))?;
+        let name = d.file_name().to_string_lossy().to_string();
+        let p = d.path();
+
+        // 残留的临时符号链接
+        if let Some(t) = name.strip_prefix(&format!("{}.", SYMLINK_LATEST))
+            && re_t.is_match(t)
+            && p.symlink_metadata().is_ok_and(|m| m.is_symlink())
+        {
+            o.push(Problem::LatestTmp { name });
+            continue;
+        }
+        if !re_year.is_match(&name) || !p.is_dir() {
+            continue;
+        }
+
+        // 检查年目录
+        let subvol = b.list(&p)?;
+        let mut empty = true;
+        for i in read_dir(&p).map_err(PmbsError::io(&p))? {
+            let d = i.map_err(PmbsError::io(&p))?;
+            empty = false;
+            let t_name = d.file_name().to_string_lossy().to_string();
+            if !re_t.is_match(&t_name) {
+                continue;
+            }
+            let path = format!("{}/{}", name, t_name);
+            let p_t = d.path();
+            let t = match u64::from_str(&t_name) {
+                Ok(t) if p_t.symlink_metadata().is_ok_and(|m| m.is_dir()) => t,
+                _ => {
+                    o.push(Problem::NotSubvol { path });
+                    continue;
+                }
+            };
+            if !subvol.contains(&p_t) {
+                o.push(Problem::NotSubvol { path });
+                continue;
+            }
+            // 普通目录模拟的快照不是只读的
+            if c.backend != BACKEND_DIR && !b.show(&p_t)?.readonly {
+                o.push(Problem::NotReadonly { path: path.clone() });
+            }
+
+            let year = get_year(t).to_string();
+            let path = if year != name {
+                let expect = format!("{}/{}", year, t);
+                o.push(Problem::WrongYear {
+                    path,
+                    expect: expect.clone(),
+                });
+                expect
+            } else {
+                path
+            };
+            if newest.as_ref().is_none_or(|x| t > x.0) {
+                newest = Some((t, path));
+            }
+        }
+        if empty {
+            o.push(Problem::EmptyYear { year: name });
+        }
+    }
+
+    // 检查 latest
+    let latest = dir.join(SYMLINK_LATEST);
+    let expect = newest.map(|x| x.1);
+    match latest.symlink_metadata() {
+        Ok(m) if !m.is_symlink() => o.push(Problem::LatestNotSymlink),
+        Ok(_) => {
+            let to = read_link(&latest)
+                .map_err(PmbsError::io(&latest))?
+                .to_string_lossy()
+                .to_string();
+            if !dir.join(&to).is_dir() {
+                o.push(Problem::LatestDangling { to, expect });
+            } else if let Some(expect) = expect
+                && to != expect
+            {
+                o.push(Problem::LatestNotNewest { to, expect });
+            }
+        }
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {
+            if let Some(expect) = expect {
+                o.push(Problem::LatestMissing { expect });
+            }
+        }
+        Err(e) => {
+            return Err(PmbsError::Io(latest.to_string_lossy().to_string(), e));
+        }
+    }
+    Ok(o)
+}
+
+/// 修复一个问题
+fn repair(pmbs: &SafeDir, p: &Problem) -> PmbsResult<()> {
+    let dir = pmbs.path.as_path();
+    match p {
+        Problem::LatestMissing { expect }
+        | Problem::LatestNotNewest { expect, .. }
+        | Problem::LatestDangling {
+            expect: Some(expect),
+            ..
+        } => {
+            // 检查时已经验证过格式
+            let (year, t) = expect.split_once('/').unwrap();
+            write_latest(
+                pmbs,
+                i32::from_str(year).unwrap(),
+                u64::from_str(t).unwrap(),
+            )
+        }
+        Problem::LatestDangling { expect: None, .. } => {
+            info!("rm {}", dir.join(SYMLINK_LATEST).to_string_lossy());
+            pmbs.remove_file(SYMLINK_LATEST).map(|_| ())
+        }
+        Problem::LatestTmp { name } => {
+            info!("rm {}", dir.join(name).to_string_lossy());
+            pmbs.remove_file(name).map(|_| ())
+        }
+        Problem::WrongYear { path, expect } => {
+            let (from, to) = (dir.join(path), dir.join(expect));
+            // 检查时已经验证过格式: 年目录/时间戳
+            let (from_year, from_t) = path.split_once('/').unwrap();
+            let (to_year, to_t) = expect.split_once('/').unwrap();
+            // 打开 (创建) 年目录 (不跟随符号链接)
+            let not_found = |p: &Path| PmbsError::io(p)(io::ErrorKind::NotFound.into());
+            let a = pmbs
+                .open_dir(from_year, false)?
+                .ok_or_else(|| not_found(&from))?;
+            let b = pmbs
+                .open_dir(to_year, true)?
+                .ok_or_else(|| not_found(&to))?;
+            info!("mv {} -> {}", from.to_string_lossy(), to.to_string_lossy());
+            a.rename(from_t, &b, to_t)?;
+            // 固定的快照: 更新路径
+            let mut list = read_pin(dir);
+            let mut changed = false;
+            for i in list.pin.iter_mut().filter(|x| x.path == *path) {
+                i.path = expect.clone();
+                changed = true;
+            }
+            if changed {
+                write_pin(pmbs, &list)?;
+            }
+            Ok(())
+        }
+        Problem::EmptyYear { year } => {
+            let p = dir.join(year);
+            info!("rmdir {}", p.to_string_lossy());
+            remove_dir(&p).map_err(PmbsError::io(&p))
+        }
+        Problem::LatestNotSymlink | Problem::NotSubvol { .. } | Problem::NotReadonly { .. } => {
+            Ok(())
+        }
+    }
+}
+
+/// 检查快照保存目录, 并修复可以修复的问题
+///
+/// 先修复快照路径 (比如年目录错误), 然后重新检查, 再修复 latest 和空的年目录.
+///
+/// 返回: (问题, 是否已修复)
+pub fn fsck_repair(c: &ConfigEnv, pmbs: &SafeDir) -> PmbsResult<Vec<(Problem, bool)>> {
+    let dir = pmbs.path.as_path();
+    let mut o = Vec::new();
+    for i in fsck_check(c, dir)? {
+        if i.is_final() {
+            continue;
+        }
+        let ok = i.can_repair();
+        if ok {
+            repair(pmbs, &i)?;
+        }
+        o.push((i, ok));
+    }
+    for i in fsck_check(c, dir)? {
+        if !i.is_final() {
+            if !o.iter().any(|x| x.0 == i) {
+                o.push((i, false));
+            }
+            continue;
+        }
+        repair(pmbs, &i)?;
+        o.push((i, true));
+    }
+    Ok(o)
+}
+
+#[cfg(test)]
+mod test {
+    use std::{fs::create_dir_all, os::unix::fs::symlink};
+
+    use super::*;
+    use crate::test_util::TmpDir;
+
+    /// 使用普通目录, 测试 检查 -> 修复 -> 再次检查
+    #[test]
+    fn check_repair() {
+        let tmp = TmpDir::new("fsck");
+        let c = tmp.env();
+        let dir = tmp.join(".pmbs");
+        // 年目录错误的快照 (最新), 空的年目录, 残留的临时符号链接, latest 指向不存在的快照
+        let (t1, t2) = (1756392923, 1756400000);
+        create_dir_all(dir.join(format!("{}/{}", get_year(t1), t1))).unwrap();
+        create_dir_all(dir.join(format!("2020/{}", t2))).unwrap();
+        create_dir_all(dir.join("2021")).unwrap();
+        symlink("2025/1", dir.join(SYMLINK_LATEST)).unwrap();
+        symlink("2025/1", dir.join(format!("{}.{}", SYMLINK_LATEST, t1))).unwrap();
+
+        let expect = format!("{}/{}", get_year(t2), t2);
+        let mut p = fsck_check(&c, &dir).unwrap();
+        p.sort_by_key(|x| x.describe());
+        assert_eq!(
+            p,
+            vec![
+                Problem::EmptyYear {
+                    year: "2021".into()
+                },
+                Problem::LatestDangling {
+                    to: "2025/1".into(),
+                    expect: Some(expect.clone())
+                },
+                Problem::LatestTmp {
+                    name: format!("{}.{}", SYMLINK_LATEST, t1)
+                },
+                Problem::WrongYear {
+                    path: format!("2020/{}", t2),
+                    expect: expect.clone()
+                },
+            ]
+        );
+
+        let r = fsck_repair(&c, &SafeDir::open_pmbs(&dir, false).unwrap()).unwrap();
+        assert!(r.iter().all(|x| x.1));
+        // 移动快照之后, 2020 也是空的年目录
+        assert!(r.iter().any(|x| x.0
+            == Problem::EmptyYear {
+                year: "2020".into()
+            }));
+        assert_eq!(fsck_check(&c, &dir).unwrap(), vec![]);
+        assert_eq!(
+            read_link(dir.join(SYMLINK_LATEST))
+                .unwrap()
+                .to_string_lossy(),
+            expect
+        );
+
+        // 普通文件不是快照 (不修复)
+        std::fs::write(dir.join(format!("{}/{}", get_year(t1), t1 + 1)), "").unwrap();
+        let r = fsck_repair(&c, &SafeDir::open_pmbs(&dir, false).unwrap()).unwrap();
+        assert_eq!(r.len(), 1);
+        assert!(!r[0].1 && !r[0].0.can_repair());
+    }
+}
@@ -0,0 +1,126 @@
+//! 创建快照前后执行的命令 (hook)
+use std::{path::Path, process::Command};
+
+use log::{error, info};
+
+use crate::{cli::sh_run_timeout, config::PmbsConfig, error::PmbsResult};
+
+// 传递给命令的环境变量
+const ENV_PMBS_HOOK: &str = "PMBS_HOOK";
+const ENV_PMBS_SUBVOL: &str = "PMBS_SUBVOL";
+const ENV_PMBS_SNAPSHOT_PATH: &str = "PMBS_SNAPSHOT_PATH";
+const ENV_PMBS_T: &str = "PMBS_T";
+
+/// pre_snapshot
+pub const HOOK_PRE_SNAPSHOT: &str = "pre_snapshot";
+/// post_snapshot
+pub const HOOK_POST_SNAPSHOT: &str = "post_snapshot";
+/// on_failure
+pub const HOOK_ON_FAILURE: &str = "on_failure";
+
+/// 执行 hook 命令的环境
+pub struct Hook<'a> {
+    /// 配置
+    pub config: &'a PmbsConfig,
+    /// 快照路径, 比如 /home/.pmbs/2025/1756392923
+    pub to: &'a Path,
+    /// 快照时间戳
+    pub t: u64,
+}
+
+impl Hook<'_> {
+    /// 依次执行一组命令
+    ///
+    /// + `stop`: 遇到失败的命令后, 是否停止执行后面的命令
+    ///
+    /// 返回: 第一个失败的命令的错误
+    pub fn run(&self, name: &str, list: &[Vec<String>], stop: bool) -> PmbsResult<()> {
+        let mut r = Ok(());
+        for i in list {
+            info!("{}  {:?}", name, i);
+
+            let mut c = Command::new(&i[0]);
+            c.args(&i[1..])
+                .env(ENV_PMBS_HOOK, name)
+                .env(ENV_PMBS_SUBVOL, &self.config.subvol)
+                .env(ENV_PMBS_SNAPSHOT_PATH, self.to)
+                .env(ENV_PMBS_T, format!("{}", self.t));
+            if let Err(e) = sh_run_timeout(c, self.config.hook_timeout_s) {
+                error!("{} failed  {}", name, e);
+                if r.is_ok() {
+                    r = Err(e);
+                }
+                if stop {
+                    break;
+                }
+            }
+        }
+        r
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        fs::{create_dir_all, read_link, remove_file},
+        path::PathBuf,
+    };
+
+    use crate::{
+        clean::ls_snapshot,
+        config::{HookFailPolicy, PmbsConfig},
+        snapshot::{SYMLINK_LATEST, make_snapshot},
+        test_util::TmpDir,
+    };
+
+    /// pre_snapshot 失败, 继续创建快照: 执行 on_failure
+    #[test]
+    fn hook_continue() {
+        let tmp = TmpDir::new("hook");
+        let subvol = tmp.join("subvol");
+        create_dir_all(&subvol).unwrap();
+
+        let c = tmp.env();
+        let mut config = PmbsConfig::new_subvol(&subvol.to_string_lossy());
+        config.snapshot_dir = Some(tmp.join(".pmbs-store/home").to_string_lossy().to_string());
+        let flag = tmp.join("on_failure");
+        config.pre_snapshot = vec![vec!["false".into()]];
+        config.on_failure = vec![vec!["touch".into(), flag.to_string_lossy().to_string()]];
+        config.pre_snapshot_fail = HookFailPolicy::Continue;
+        make_snapshot(&c, &config, &Default::default()).unwrap();
+        assert_eq!(ls_snapshot(&config.dir_pmbs()).unwrap().len(), 1);
+        assert!(flag.exists());
+
+        // abort: 不创建快照, 也执行 on_failure
+        remove_file(&flag).unwrap();
+        config.pre_snapshot_fail = HookFailPolicy::Abort;
+        assert!(make_snapshot(&c, &config, &Default::default()).is_err());
+        assert!(flag.exists());
+    }
+
+    /// post_snapshot 失败: 快照已经创建, 仍然写入元数据和 latest, 然后执行 on_failure
+    #[test]
+    fn hook_post_fail() {
+        let tmp = TmpDir::new("hook-post");
+        let subvol = tmp.join("subvol");
+        create_dir_all(&subvol).unwrap();
+
+        let c = tmp.env();
+        let mut config = PmbsConfig::new_subvol(&subvol.to_string_lossy());
+        config.snapshot_dir = Some(tmp.join(".pmbs-store/home").to_string_lossy().to_string());
+        let flag = tmp.join("on_failure");
+        config.post_snapshot = vec![vec!["false".into()]];
+        config.on_failure = vec![vec!["touch".into(), flag.to_string_lossy().to_string()]];
+        assert!(make_snapshot(&c, &config, &Default::default()).is_err());
+        assert!(flag.exists());
+
+        let dir = config.dir_pmbs();
+        let list = ls_snapshot(&dir).unwrap();
+        assert_eq!(list.len(), 1);
+        assert!(list[0].meta.is_some());
+        assert_eq!(
+            read_link(dir.join(SYMLINK_LATEST)).unwrap(),
+            PathBuf::from(&list[0].path)
+        );
+    }
+}
@@ -0,0 +1,84 @@
+//! 检查创建快照的间隔时间 (interval)
+use log::{debug, info, warn};
+
+use crate::{clean::ls_snapshot, config::PmbsConfig};
+
+/// 允许的时间误差 (秒)
+///
+/// systemd timer 触发时间不精确, 比如 latest 快照在 12:00:01 创建,
+/// 下次在 12:15:00 触发时, 仍然应该创建快照.
+pub const INTERVAL_TOLERANCE_S: u64 = 10;
+
+/// latest 快照是否在将来 (超过允许的误差), 比如系统时间被调回
+pub fn is_future(latest_t: u64, t: u64) -> bool {
+    latest_t > t + INTERVAL_TOLERANCE_S
+}
+
+/// 距离 latest 快照是否还不到间隔时间
+///
+/// latest 在将来时返回 false (创建快照), 否则要等到系统时间追上之后才能创建快照.
+pub fn in_interval(latest_t: u64, interval_s: u64, t: u64) -> bool {
+    !is_future(latest_t, t) && t + INTERVAL_TOLERANCE_S < latest_t + interval_s
+}
+
+/// 检查是否跳过本次快照: 距离 latest 快照还不到 interval
+pub fn check_interval(config: &PmbsConfig, t: u64) -> bool {
+    if 0 == config.interval_s {
+        return false;
+    }
+    let subvol = &config.subvol;
+    let latest = match ls_snapshot(&config.dir_pmbs())
+        .unwrap_or_default()
+        .into_iter()
+        .find(|x| x.latest)
+    {
+        Some(latest) => latest,
+        None => {
+            debug!("no latest snapshot  {}", subvol);
+            return false;
+        }
+    };
+    if is_future(latest.t, t) {
+        warn!(
+            "time error !  latest {} > {}  ({})  {}",
+            latest.t,
+            t,
+            latest.t - t,
+            subvol
+        );
+        return false;
+    }
+    if in_interval(latest.t, config.interval_s, t) {
+        info!(
+            "skip snapshot (interval)  {}  interval = {}s, latest = {} ({}s ago)",
+            subvol,
+            config.interval_s,
+            latest.path,
+            t.saturating_sub(latest.t)
+        );
+        true
+    } else {
+        false
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn interval() {
+        let t = 1756392923;
+        assert!(in_interval(t, 900, t));
+        assert!(in_interval(t, 900, t + 60));
+        assert!(in_interval(t, 900, t + 889));
+        assert!(!in_interval(t, 900, t + 890));
+        assert!(!in_interval(t, 900, t + 900));
+        assert!(!in_interval(t, 900, t + 3600));
+        // 系统时间错误: latest 在将来, 不跳过
+        assert!(!in_interval(t + 60, 900, t));
+        assert!(!in_interval(t + 3600, 900, t));
+        // 允许的误差之内
+        assert!(in_interval(t + 10, 900, t));
+    }
+}
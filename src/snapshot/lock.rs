@@ -0,0 +1,164 @@
+//! 快照保存目录的锁文件 /.pmbs/.lock
+//!
+//! 创建快照, 清理快照, 以及修改 .pmbs 目录的其它命令, 都需要先获取锁,
+//! 避免同时操作同一个 subvol.
+//!
+//! 锁文件通过 `.pmbs` 的文件描述符打开 (不跟随符号链接), 见 [`SafeDir::open_file`].
+use std::{
+    fs::File,
+    io::Write,
+    os::unix::fs::FileExt,
+    path::{Path, PathBuf},
+    str::FromStr,
+    thread::sleep,
+    time::{Duration, Instant},
+};
+
+use log::{debug, warn};
+use rustix::{
+    fs::{FlockOperation, flock},
+    io::Errno,
+};
+
+use super::SafeDir;
+use crate::error::{PmbsError, PmbsResult};
+
+/// 锁文件 /.pmbs/.lock
+pub const FILE_LOCK: &str = ".lock";
+
+/// 已经获取的锁 (释放时自动解锁)
+#[derive(Debug)]
+pub struct PmbsLock {
+    /// 锁文件
+    file: File,
+    /// 锁文件路径
+    path: PathBuf,
+}
+
+/// 读取锁文件中的 PID
+fn read_pid(f: &File) -> Option<u32> {
+    let mut buf = [0; 32];
+    let n = f.read_at(&mut buf, 0).ok()?;
+    u32::from_str(std::str::from_utf8(&buf[..n]).ok()?.trim()).ok()
+}
+
+/// 检查进程是否存在
+fn is_running(pid: u32) -> bool {
+    Path::new(&format!("/proc/{}", pid)).exists()
+}
+
+impl PmbsLock {
+    /// 获取指定目录 (比如 /home/.pmbs) 的锁, 最多等待 `timeout` 秒
+    ///
+    /// 锁被占用 (等待超时) 时返回 [`PmbsError::Lock`],
+    /// 锁文件是符号链接 (或者硬链接) 时返回 [`PmbsError::Safety`]
+    pub fn new(dir: &SafeDir, timeout: u64) -> PmbsResult<Self> {
+        let path = dir.path.join(FILE_LOCK);
+        let file = dir.open_file(FILE_LOCK)?;
+
+        let start = Instant::now();
+        let mut waiting = false;
+        loop {
+            match flock(&file, FlockOperation::NonBlockingLockExclusive) {
+                Ok(()) => break,
+                Err(Errno::WOULDBLOCK) | Err(Errno::INTR) => {}
+                Err(e) => {
+                    return Err(PmbsError::io(&path)(e.into()));
+                }
+            }
+            // 锁被占用
+            let pid = read_pid(&file);
+            if !waiting {
+                waiting = true;
+                warn!(
+                    "lock busy, wait {}s  {}  pid = {:?}",
+                    timeout,
+                    path.to_string_lossy(),
+                    pid
+                );
+            }
+            if start.elapsed() >= Duration::from_secs(timeout) {
+                let p = path.to_string_lossy();
+                return Err(PmbsError::Lock(match pid {
+                    Some(pid) if is_running(pid) => format!("{}  held by pid {}", p, pid),
+                    Some(pid) => format!(
+                        "{}  held by pid {} (not running, maybe a child process)",
+                        p, pid
+                    ),
+                    None => p.to_string(),
+                }));
+            }
+            sleep(Duration::from_millis(100));
+        }
+
+        // 解锁时会清空锁文件, 如果其中仍然有 PID, 说明之前的进程没有正常退出
+        if let Some(pid) = read_pid(&file)
+            && pid != std::process::id()
+        {
+            warn!("stale lock  {}  pid = {}", path.to_string_lossy(), pid);
+        }
+        let mut lock = Self { file, path };
+        lock.write_pid().map_err(PmbsError::io(&lock.path))?;
+        debug!("lock {}", lock.path.to_string_lossy());
+        Ok(lock)
+    }
+
+    fn write_pid(&mut self) -> std::io::Result<()> {
+        self.file.set_len(0)?;
+        self.file
+            .write_all(format!("{}\n", std::process::id()).as_bytes())
+    }
+}
+
+impl Drop for PmbsLock {
+    fn drop(&mut self) {
+        debug!("unlock {}", self.path.to_string_lossy());
+        let _ = self.file.set_len(0);
+        let _ = flock(&self.file, FlockOperation::Unlock);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_util::TmpDir;
+    use std::{
+        fs::{hard_link, read_to_string, write},
+        os::unix::fs::symlink,
+    };
+
+    #[test]
+    fn lock() {
+        let tmp = TmpDir::new("lock");
+        let dir = SafeDir::open_pmbs(&tmp.join(".pmbs"), true).unwrap();
+        let pid = || read_pid(&File::open(dir.path.join(FILE_LOCK)).unwrap());
+        // 之前的进程没有正常退出
+        write(dir.path.join(FILE_LOCK), "4194304\n").unwrap();
+
+        let a = PmbsLock::new(&dir, 0).unwrap();
+        assert_eq!(pid(), Some(std::process::id()));
+        // 锁被占用 (flock 对不同的 open file description 互斥)
+        assert!(matches!(PmbsLock::new(&dir, 0), Err(PmbsError::Lock(_))));
+        drop(a);
+        assert_eq!(pid(), None);
+        assert!(PmbsLock::new(&dir, 0).is_ok());
+    }
+
+    /// 锁文件是指向别的文件的符号链接 (或者硬链接): 拒绝, 不能清空别的文件
+    #[test]
+    fn lock_symlink() {
+        let tmp = TmpDir::new("lock-symlink");
+        let victim = tmp.join("shadow");
+        write(&victim, "secret").unwrap();
+
+        let dir = SafeDir::open_pmbs(&tmp.join(".pmbs"), true).unwrap();
+        symlink(&victim, dir.path.join(FILE_LOCK)).unwrap();
+        assert!(matches!(PmbsLock::new(&dir, 0), Err(PmbsError::Safety(_))));
+
+        let dir = SafeDir::open_pmbs(&tmp.join("b/.pmbs"), true).unwrap();
+        hard_link(&victim, dir.path.join(FILE_LOCK)).unwrap();
+        assert!(matches!(PmbsLock::new(&dir, 0), Err(PmbsError::Safety(_))));
+
+        assert_eq!(read_to_string(&victim).unwrap(), "secret");
+    }
+}
@@ -0,0 +1,162 @@
+//! 快照元数据 (/.pmbs/meta/T.json)
+use std::{
+    env,
+    fs::read_to_string,
+    io::ErrorKind,
+    path::{Path, PathBuf},
+};
+
+use log::{debug, error, warn};
+use serde::{Deserialize, Serialize};
+
+use super::{DIR_META, SafeDir};
+use crate::error::{PmbsError, PmbsResult};
+
+// PMBS_TRIGGER=hook
+const ENV_PMBS_TRIGGER: &str = "PMBS_TRIGGER";
+
+/// 创建快照的触发方式
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Trigger {
+    /// 定期自动创建 (pmbs config snapshot)
+    #[default]
+    Timer,
+    /// 手动创建 (pmbs snapshot)
+    Manual,
+    /// 由其它程序调用 (比如软件包管理器的 hook)
+    Hook,
+}
+
+impl Trigger {
+    /// 环境变量 PMBS_TRIGGER 可以覆盖默认的触发方式
+    pub fn from_env(default: Self) -> Self {
+        match env::var(ENV_PMBS_TRIGGER).as_deref() {
+            Ok("timer") => Self::Timer,
+            Ok("manual") => Self::Manual,
+            Ok("hook") => Self::Hook,
+            Ok(t) => {
+                warn!("bad {} = {}", ENV_PMBS_TRIGGER, t);
+                default
+            }
+            Err(_) => default,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Timer => "timer",
+            Self::Manual => "manual",
+            Self::Hook => "hook",
+        }
+    }
+}
+
+/// 快照元数据
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SnapshotMeta {
+    /// 配置文件名 (手动创建快照时没有)
+    #[serde(default)]
+    pub config: Option<String>,
+    /// 触发方式
+    #[serde(default)]
+    pub trigger: Trigger,
+    /// 用户标签 (备注)
+    #[serde(default)]
+    pub label: Option<String>,
+    /// 主机名
+    #[serde(default)]
+    pub hostname: String,
+    /// pmbs 版本
+    #[serde(default)]
+    pub version: String,
+    /// 快照的 btrfs generation (Gen at creation)
+    #[serde(default)]
+    pub generation: Option<u64>,
+    /// 快照的 UUID
+    #[serde(default)]
+    pub uuid: Option<String>,
+    /// 快照的 parent UUID (创建快照时 subvol 的 UUID)
+    #[serde(default)]
+    pub parent_uuid: Option<String>,
+    /// 创建快照用时 (毫秒)
+    #[serde(default)]
+    pub duration_ms: u64,
+}
+
+/// 获取主机名
+pub fn get_hostname() -> String {
+    for p in ["/proc/sys/kernel/hostname", "/etc/hostname"] {
+        if let Ok(s) = read_to_string(p) {
+            return s.trim().to_string();
+        }
+    }
+    String::new()
+}
+
+/// 元数据文件路径, 比如 /home/.pmbs/meta/1756392923.json
+pub fn meta_path(dir: &Path, t: u64) -> PathBuf {
+    let mut p = dir.to_path_buf();
+    p.push(DIR_META);
+    p.push(meta_name(t));
+    p
+}
+
+/// 元数据文件名, 比如 1756392923.json
+fn meta_name(t: u64) -> String {
+    format!("{}.json", t)
+}
+
+/// 写入元数据 (write-replace, 不跟随符号链接)
+pub fn write_meta(dir: &SafeDir, t: u64, meta: &SnapshotMeta) -> PmbsResult<()> {
+    let p = meta_path(&dir.path, t);
+    debug!("write meta {}", p.to_string_lossy());
+
+    let text = serde_json::to_string_pretty(meta).unwrap();
+    match dir.open_dir(DIR_META, true)? {
+        Some(d) => d.write_file(&meta_name(t), text.as_bytes()),
+        None => Err(PmbsError::io(&p)(ErrorKind::NotFound.into())),
+    }
+}
+
+/// 解析元数据
+fn parse_meta(p: &Path, s: &[u8]) -> Option<SnapshotMeta> {
+    match serde_json::from_slice(s) {
+        Ok(m) => Some(m),
+        Err(e) => {
+            error!("can not parse meta {}  {:?}", p.to_string_lossy(), e);
+            None
+        }
+    }
+}
+
+/// 读取元数据
+pub fn read_meta(dir: &Path, t: u64) -> Option<SnapshotMeta> {
+    let p = meta_path(dir, t);
+    parse_meta(&p, read_to_string(&p).ok()?.as_bytes())
+}
+
+/// 读取元数据 (不跟随符号链接), 用于删除快照之前的检查
+pub fn read_meta_safe(dir: &SafeDir, t: u64) -> Option<SnapshotMeta> {
+    let p = meta_path(&dir.path, t);
+    let d = dir.open_dir(DIR_META, false).ok()??;
+    parse_meta(&p, &d.read_file(&meta_name(t)).ok()??)
+}
+
+/// 删除元数据 (快照已删除)
+pub fn rm_meta(dir: &SafeDir, t: u64) {
+    let p = meta_path(&dir.path, t);
+    let r = match dir.open_dir(DIR_META, false) {
+        Ok(Some(d)) => d.remove_file(&meta_name(t)),
+        r => r.map(|_| false),
+    };
+    match r {
+        Ok(true) => {
+            debug!("rm meta {}", p.to_string_lossy());
+        }
+        Ok(false) => {}
+        Err(e) => {
+            error!("can not rm meta {}  {:?}", p.to_string_lossy(), e);
+        }
+    }
+}
@@ -1,31 +1,119 @@
 //! 创建 btrfs 快照
 use std::{
-    fs::{create_dir_all, rename},
-    os::unix::fs::symlink,
-    path::PathBuf,
-    process::{Command, ExitCode},
+    io,
+    path::{Path, PathBuf},
+    thread::sleep,
+    time::{Duration, Instant},
 };
 
-use log::{debug, info, warn};
+use log::{debug, error, info, warn};
 
-use crate::{clean::ls_snapshot, cli::sh_run, config::ConfigEnv};
+use crate::{
+    backend::get_backend,
+    clean::ls_snapshot,
+    config::{ConfigEnv, HookFailPolicy, PmbsConfig},
+    error::{PmbsError, PmbsResult},
+};
 
+mod fsck;
+mod hook;
+mod interval;
+mod lock;
+mod meta;
+mod secure;
+mod space;
 mod t;
+mod unchanged;
+
+use hook::{HOOK_ON_FAILURE, HOOK_POST_SNAPSHOT, HOOK_PRE_SNAPSHOT, Hook};
+use meta::{get_hostname, write_meta};
+
+pub use fsck::{Problem, fsck_check, fsck_repair};
+pub use interval::{check_interval, in_interval};
+pub use lock::{FILE_LOCK, PmbsLock};
+pub use meta::{SnapshotMeta, Trigger, read_meta, read_meta_safe, rm_meta};
+pub use secure::{FileId, SafeDir};
+pub use space::{Space, get_space};
+pub use t::{
+    DIR_META, DIR_PMBS, SYMLINK_LATEST, format_t, format_t_local, get_t, get_year, parse_snapshot_t,
+};
+
+/// 创建 (替换) latest 符号链接, 指向快照 `year/t`
+///
+/// 先创建临时符号链接 `latest.T`, 然后重命名 (write-replace).
+pub fn write_latest(dir: &SafeDir, year: i32, t: u64) -> PmbsResult<()> {
+    // subvol/.pmbs/latest
+    let latest = dir.path.join(SYMLINK_LATEST);
+    // subvol/.pmbs/latest.1756392923
+    let latest_tmp = format!("{}.{}", SYMLINK_LATEST, t);
+
+    let mut link_to = PathBuf::new();
+    link_to.push(format!("{}", year));
+    link_to.push(format!("{}", t));
+    info!(
+        "symlink {} -> {}",
+        latest.to_string_lossy(),
+        link_to.to_string_lossy()
+    );
+    // write, replace
+    dir.write_symlink(&link_to, SYMLINK_LATEST, &latest_tmp)
+}
 
-pub use t::{DIR_PMBS, SYMLINK_LATEST, format_t, format_t_local, get_t, get_year};
+/// 创建快照的选项
+#[derive(Debug, Clone, Default)]
+pub struct SnapshotOpt {
+    /// 配置文件名
+    pub config: Option<String>,
+    /// 触发方式
+    pub trigger: Trigger,
+    /// 用户标签 (备注)
+    pub label: Option<String>,
+    /// 检查间隔时间 `interval` (systemd timer)
+    pub interval: bool,
+}
+
+/// 获取快照的时间戳 (获取锁之后)
+///
+/// 两个进程在同一秒内等待同一个锁时, 得到相同的时间戳:
+/// 这个快照已经存在时, 等待到下一秒.
+fn next_t(p: &Path) -> u64 {
+    let mut t = get_t();
+    // 最多等待 2 秒 (系统时间可能被调回, 不能一直等待)
+    for _ in 0..20 {
+        let to = p.join(get_year(t).to_string()).join(t.to_string());
+        if to.symlink_metadata().is_err() {
+            break;
+        }
+        debug!("snapshot exist, wait  {}", to.to_string_lossy());
+        sleep(Duration::from_millis(100));
+        t = get_t();
+    }
+    t
+}
 
 /// 创建指定 subvol 的快照
-pub fn make_snapshot(config: &ConfigEnv, subvol: &str) -> Result<(), ExitCode> {
-    // 获取当前时间, 比如 1756392923
-    let t = get_t();
+pub fn make_snapshot(c: &ConfigEnv, config: &PmbsConfig, opt: &SnapshotOpt) -> PmbsResult<()> {
+    let subvol = config.subvol.as_str();
+    // subvol/.pmbs (或者 snapshot_dir)
+    let p = config.dir_pmbs();
+    // 获取锁 (不跟随符号链接, 检查所有者)
+    let pmbs = SafeDir::open_pmbs(&p, true)?;
+    let _lock = PmbsLock::new(&pmbs, config.lock_timeout_s)?;
+
+    // 获取当前时间, 比如 1756392923 (等待锁的时间可能很长, 所以在获取锁之后)
+    let t = next_t(&p);
     let year = get_year(t);
     let now = format_t(t);
     debug!("snapshot t = {}  {}", t, now);
 
-    // 目标目录, 比如 /.pmbs/2025/1756392923
-    // subvol/.pmbs
-    let mut p = PathBuf::from(subvol);
-    p.push(DIR_PMBS);
+    // 等待锁的时候, 别的进程可能已经创建了快照: 再次检查间隔时间
+    if opt.interval && check_interval(config, t) {
+        return Ok(());
+    }
+    // 检查 subvol 是否有变化
+    if config.skip_unchanged && unchanged::check_unchanged(c, config, t) {
+        return Ok(());
+    }
     // subvol/.pmbs/2025
     let mut y = p.clone();
     y.push(format!("{}", year));
@@ -33,50 +121,110 @@ pub fn make_snapshot(config: &ConfigEnv, subvol: &str) -> Result<(), ExitCode> {
     let mut to = y.clone();
     to.push(format!("{}", t));
 
+    // 创建快照之前执行的命令
+    let hook = Hook { config, to: &to, t };
+    // 是否已经执行过 on_failure (只执行一次)
+    let mut failed = false;
+    if let Err(e) = hook.run(HOOK_PRE_SNAPSHOT, &config.pre_snapshot, true) {
+        match config.pre_snapshot_fail {
+            HookFailPolicy::Abort => {
+                error!("abort snapshot  {}", subvol);
+                // 之前的命令可能已经执行成功, 仍然需要恢复
+                let _ = hook.run(HOOK_POST_SNAPSHOT, &config.post_snapshot, false);
+                let _ = hook.run(HOOK_ON_FAILURE, &config.on_failure, false);
+                return Err(e);
+            }
+            HookFailPolicy::Continue => {
+                warn!("pre_snapshot failed, continue  {}", subvol);
+                let _ = hook.run(HOOK_ON_FAILURE, &config.on_failure, false);
+                failed = true;
+            }
+        }
+    }
+
     info!("snapshot {} -> {}", subvol, to.to_string_lossy());
     // 创建目录
-    create_dir_all(y).unwrap();
-
-    // 执行命令, 比如 btrfs subvol snapshot -r /home /home/.pmbs/2025/1756392923
-    let mut c = Command::new(config.bin_btrfs.clone());
-    c.arg("subvol")
-        .arg("snapshot")
-        .arg("-r")
-        .arg(subvol)
-        .arg(to);
-    let code = sh_run(c);
-    if 0 != code {
-        return Err(ExitCode::from(1));
+    let year_dir = match pmbs.open_dir(&year.to_string(), true)? {
+        Some(d) => d,
+        None => {
+            return Err(PmbsError::io(&y)(io::ErrorKind::NotFound.into()));
+        }
+    };
+    // 执行命令之前, 再次检查目录没有被替换
+    pmbs.id.check_same(&p)?;
+    year_dir.id.check_same(&y)?;
+
+    // 比如 btrfs subvol snapshot -r /home /home/.pmbs/2025/1756392923
+    let b = get_backend(c);
+    let start = Instant::now();
+    let r = b.snapshot(Path::new(subvol), &to);
+    let duration_ms = start.elapsed().as_millis() as u64;
+    if let Err(e) = r {
+        // 创建快照失败: 仍然执行之后的命令 (恢复)
+        let _ = hook.run(HOOK_POST_SNAPSHOT, &config.post_snapshot, false);
+        if !failed {
+            let _ = hook.run(HOOK_ON_FAILURE, &config.on_failure, false);
+        }
+        return Err(e);
     }
 
+    // 写入快照元数据 (UUID 用于删除之前的检查: 回滚之后 subvol 的 UUID 改变)
+    let info = b.show(&to).ok();
+    let meta = SnapshotMeta {
+        config: opt.config.clone(),
+        trigger: opt.trigger,
+        label: opt.label.clone(),
+        hostname: get_hostname(),
+        version: env!("CARGO_PKG_VERSION").into(),
+        generation: info.as_ref().map(|x| x.gen_at_creation),
+        uuid: info.as_ref().map(|x| x.uuid.clone()),
+        parent_uuid: info.and_then(|x| x.parent_uuid),
+        duration_ms,
+    };
     // subvol/.pmbs/latest
-    let mut latest = p.clone();
-    latest.push(SYMLINK_LATEST);
-    // subvol/.pmbs/latest.1756392923
-    let mut latest_tmp = p.clone();
-    latest_tmp.push(format!("{}.{}", SYMLINK_LATEST, t));
+    let w = write_meta(&pmbs, t, &meta).and_then(|_| write_latest(&pmbs, year, t));
 
-    // 创建 latest 符号链接 (write-replace)
-    let mut link_to = PathBuf::new();
-    link_to.push(format!("{}", year));
-    link_to.push(format!("{}", t));
-    info!(
-        "symlink {} -> {}",
-        latest.to_string_lossy(),
-        link_to.to_string_lossy()
-    );
-    // write
-    symlink(link_to, &latest_tmp).unwrap();
-    // replace
-    rename(&latest_tmp, latest).unwrap();
+    // 创建快照之后执行的命令 (快照已经存在: 先写入元数据和 latest)
+    let post = hook.run(HOOK_POST_SNAPSHOT, &config.post_snapshot, false);
+    if let Err(e) = w.and(post) {
+        if !failed {
+            let _ = hook.run(HOOK_ON_FAILURE, &config.on_failure, false);
+        }
+        return Err(e);
+    }
 
     // 创建快照后的检测: 是否还有更新的快照.
     // 如果存在, 可能是系统时间配置错误 !
-    let list = ls_snapshot(subvol);
-    let max_t = list.iter().map(|x| x.t).max().unwrap();
+    let list = ls_snapshot(&p)?;
+    let max_t = list.iter().map(|x| x.t).max().unwrap_or(t);
     if max_t > t {
         warn!("time error !  {} > {}  ({})", max_t, t, max_t - t);
     }
 
     Ok(())
 }
+
+#[cfg(test)]
+mod test {
+    use std::fs::create_dir_all;
+
+    use super::*;
+    use crate::{clean::ls_snapshot, test_util::TmpDir};
+
+    /// 同一秒之内连续创建快照: 时间戳不同
+    #[test]
+    fn same_second() {
+        let tmp = TmpDir::new("same-second");
+        let subvol = tmp.join("subvol");
+        create_dir_all(&subvol).unwrap();
+
+        let c = tmp.env();
+        let mut config = PmbsConfig::new_subvol(&subvol.to_string_lossy());
+        config.snapshot_dir = Some(tmp.join(".pmbs-store/home").to_string_lossy().to_string());
+        make_snapshot(&c, &config, &Default::default()).unwrap();
+        make_snapshot(&c, &config, &Default::default()).unwrap();
+        let list = ls_snapshot(&config.dir_pmbs()).unwrap();
+        assert_eq!(list.len(), 2);
+        assert_ne!(list[0].t, list[1].t);
+    }
+}
@@ -0,0 +1,393 @@
+//! 安全地访问快照保存目录 (不跟随符号链接)
+//!
+//! pmbs 通常以 root 运行, 但是 subvol 可能属于普通用户 (比如 `/home/alice`).
+//! 这个用户可以把 `.pmbs` (或者其中的年目录, 快照) 替换为符号链接,
+//! 指向别的 subvol, 使 root 在别的位置创建目录, 或者删除别的 subvol.
+//!
+//! 因此这里使用 `openat` + `O_NOFOLLOW` 逐级打开目录 (持有文件描述符),
+//! 并检查 `.pmbs` 和年目录的所有者 (必须是当前用户) 以及设备 (年目录必须和 `.pmbs` 相同).
+//! 调用 btrfs 命令仍然需要使用路径, 所以执行命令之前再次检查路径 (dev, ino) 没有改变.
+//!
+//! `.pmbs` 中的文件 (锁文件, 元数据, 固定快照列表, latest) 也通过目录的文件描述符访问:
+//! 不跟随符号链接, 临时文件使用 `O_EXCL` 创建, 然后 `renameat` 替换.
+use std::{
+    fs::{File, Metadata},
+    io::{self, Read, Write},
+    os::{fd::OwnedFd, unix::fs::MetadataExt},
+    path::{Component, Path, PathBuf},
+};
+
+use log::info;
+use rustix::{
+    fs::{AtFlags, CWD, Mode, OFlags, mkdirat, openat, renameat, symlinkat, unlinkat},
+    io::Errno,
+    process::geteuid,
+};
+
+use crate::error::{PmbsError, PmbsResult};
+
+/// 一个文件 (目录) 的身份: (设备, inode)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FileId {
+    pub dev: u64,
+    pub ino: u64,
+}
+
+impl FileId {
+    /// 重新读取路径 (最后一级不跟随符号链接), 检查是否仍然是同一个文件
+    ///
+    /// 用于执行 btrfs 命令之前, 缩小检查和使用之间 (TOCTOU) 的时间窗口.
+    pub fn check_same(&self, p: &Path) -> PmbsResult<()> {
+        let m = p.symlink_metadata().map_err(PmbsError::io(p))?;
+        if m.dev() != self.dev || m.ino() != self.ino {
+            return Err(PmbsError::Safety(format!(
+                "path changed  {}",
+                p.to_string_lossy()
+            )));
+        }
+        Ok(())
+    }
+}
+
+/// 已打开的目录 (O_PATH 文件描述符)
+#[derive(Debug)]
+pub struct SafeDir {
+    fd: OwnedFd,
+    /// 路径 (用于显示, 以及调用 btrfs 命令)
+    pub path: PathBuf,
+    /// 打开时的身份
+    pub id: FileId,
+}
+
+/// 打开一级 (不跟随符号链接), 不存在时返回 None
+fn open_nofollow(dir: &OwnedFd, name: &Path) -> io::Result<Option<(OwnedFd, Metadata)>> {
+    let flags = OFlags::PATH | OFlags::NOFOLLOW | OFlags::CLOEXEC;
+    let fd = match openat(dir, name, flags, Mode::empty()) {
+        Ok(fd) => fd,
+        Err(Errno::NOENT) => {
+            return Ok(None);
+        }
+        Err(e) => {
+            return Err(e.into());
+        }
+    };
+    // O_PATH | O_NOFOLLOW 打开符号链接时, 得到符号链接本身
+    let f = File::from(fd);
+    let m = f.metadata()?;
+    Ok(Some((f.into(), m)))
+}
+
+impl SafeDir {
+    /// 打开快照保存目录 (比如 `/home/.pmbs`), `create` 时不存在则创建
+    ///
+    /// 之前的各级目录 (配置文件中的路径) 允许属于 root 或当前用户的符号链接,
+    /// 最后一级不能是符号链接, 并且必须属于当前用户.
+    pub fn open_pmbs(dir: &Path, create: bool) -> PmbsResult<Self> {
+        let err = PmbsError::io(dir);
+        let uid = geteuid().as_raw();
+        let root = if dir.is_absolute() { "/" } else { "." };
+        let mut fd: OwnedFd = openat(CWD, root, OFlags::PATH | OFlags::DIRECTORY, Mode::empty())
+            .map_err(|e| err(e.into()))?;
+        let mut path = PathBuf::from(root);
+
+        let list: Vec<Component> = dir
+            .components()
+            .filter(|x| !matches!(x, Component::RootDir | Component::CurDir))
+            .collect();
+        for (i, c) in list.iter().enumerate() {
+            let name = Path::new(c.as_os_str());
+            path.push(name);
+            let last = i + 1 == list.len();
+            let next = match open_nofollow(&fd, name).map_err(PmbsError::io(&path))? {
+                Some(x) => x,
+                None if create => {
+                    info!("mkdir {}", path.to_string_lossy());
+                    mkdirat(&fd, name, Mode::from_raw_mode(0o755))
+                        .map_err(|e| PmbsError::io(&path)(e.into()))?;
+                    open_nofollow(&fd, name)
+                        .map_err(PmbsError::io(&path))?
+                        .ok_or_else(|| PmbsError::io(&path)(io::ErrorKind::NotFound.into()))?
+                }
+                None => {
+                    return Err(PmbsError::io(&path)(io::ErrorKind::NotFound.into()));
+                }
+            };
+            let (next, m) = match next {
+                // 之前的各级: 跟随可信的符号链接
+                (_, m)
+                    if m.file_type().is_symlink() && !last && (m.uid() == 0 || m.uid() == uid) =>
+                {
+                    let fd = openat(&fd, name, OFlags::PATH | OFlags::DIRECTORY, Mode::empty())
+                        .map_err(|e| PmbsError::io(&path)(e.into()))?;
+                    let m = File::from(fd.try_clone().map_err(PmbsError::io(&path))?)
+                        .metadata()
+                        .map_err(PmbsError::io(&path))?;
+                    (fd, m)
+                }
+                x => x,
+            };
+            check_dir(&path, &m)?;
+            if last && m.uid() != uid {
+                return Err(PmbsError::Safety(format!(
+                    "bad owner  {}  uid = {}",
+                    path.to_string_lossy(),
+                    m.uid()
+                )));
+            }
+            fd = next;
+        }
+
+        let m = File::from(fd.try_clone().map_err(PmbsError::io(dir))?)
+            .metadata()
+            .map_err(PmbsError::io(dir))?;
+        Ok(Self {
+            fd,
+            path: dir.to_path_buf(),
+            id: FileId {
+                dev: m.dev(),
+                ino: m.ino(),
+            },
+        })
+    }
+
+    /// 打开快照保存目录, 不存在时返回 None (不创建)
+    pub fn try_open_pmbs(dir: &Path) -> PmbsResult<Option<Self>> {
+        match Self::open_pmbs(dir, false) {
+            Ok(d) => Ok(Some(d)),
+            Err(PmbsError::Io(_, e)) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// 打开子目录 (比如年目录 `2025`), 不存在时: `create` 则创建, 否则返回 None
+    ///
+    /// 不能是符号链接, 必须属于当前用户, 并且和当前目录在同一个设备上.
+    pub fn open_dir(&self, name: &str, create: bool) -> PmbsResult<Option<Self>> {
+        let path = self.path.join(name);
+        let mut r = open_nofollow(&self.fd, Path::new(name)).map_err(PmbsError::io(&path))?;
+        if r.is_none() && create {
+            info!("mkdir {}", path.to_string_lossy());
+            mkdirat(&self.fd, name, Mode::from_raw_mode(0o755))
+                .map_err(|e| PmbsError::io(&path)(e.into()))?;
+            r = open_nofollow(&self.fd, Path::new(name)).map_err(PmbsError::io(&path))?;
+        }
+        let (fd, m) = match r {
+            Some(x) => x,
+            None => {
+                return Ok(None);
+            }
+        };
+        check_dir(&path, &m)?;
+        if m.uid() != geteuid().as_raw() {
+            return Err(PmbsError::Safety(format!(
+                "bad owner  {}  uid = {}",
+                path.to_string_lossy(),
+                m.uid()
+            )));
+        }
+        if m.dev() != self.id.dev {
+            return Err(PmbsError::Safety(format!(
+                "bad device  {}",
+                path.to_string_lossy()
+            )));
+        }
+        Ok(Some(Self {
+            fd,
+            path,
+            id: FileId {
+                dev: m.dev(),
+                ino: m.ino(),
+            },
+        }))
+    }
+
+    /// 检查子目录 (快照) 不是符号链接, 返回身份, 不存在时返回 None
+    ///
+    /// 不检查所有者 (和 subvol 相同) 和设备 (btrfs 的每个 subvol 是单独的设备).
+    pub fn stat_dir(&self, name: &str) -> PmbsResult<Option<FileId>> {
+        let path = self.path.join(name);
+        match open_nofollow(&self.fd, Path::new(name)).map_err(PmbsError::io(&path))? {
+            Some((_, m)) => {
+                check_dir(&path, &m)?;
+                Ok(Some(FileId {
+                    dev: m.dev(),
+                    ino: m.ino(),
+                }))
+            }
+            None => Ok(None),
+        }
+    }
+
+    /// 打开 (不存在则创建) 目录中的文件, 用于读写 (比如锁文件 `.lock`)
+    ///
+    /// 不跟随符号链接, 必须是普通文件, 并且没有别的硬链接.
+    pub fn open_file(&self, name: &str) -> PmbsResult<File> {
+        let path = self.path.join(name);
+        let flags = OFlags::RDWR | OFlags::CREATE | OFlags::NOFOLLOW | OFlags::CLOEXEC;
+        let fd = openat(&self.fd, name, flags, Mode::from_raw_mode(0o644))
+            .map_err(|e| at_err(&path, e))?;
+        let f = File::from(fd);
+        check_file(&path, &f.metadata().map_err(PmbsError::io(&path))?)?;
+        Ok(f)
+    }
+
+    /// 读取目录中的文件 (不跟随符号链接), 不存在时返回 None
+    pub fn read_file(&self, name: &str) -> PmbsResult<Option<Vec<u8>>> {
+        let path = self.path.join(name);
+        let flags = OFlags::RDONLY | OFlags::NOFOLLOW | OFlags::CLOEXEC;
+        let mut f = match openat(&self.fd, name, flags, Mode::empty()) {
+            Ok(fd) => File::from(fd),
+            Err(Errno::NOENT) => {
+                return Ok(None);
+            }
+            Err(e) => {
+                return Err(at_err(&path, e));
+            }
+        };
+        check_file(&path, &f.metadata().map_err(PmbsError::io(&path))?)?;
+        let mut o = Vec::new();
+        f.read_to_end(&mut o).map_err(PmbsError::io(&path))?;
+        Ok(Some(o))
+    }
+
+    /// 写入目录中的文件 (write-replace): 先写入临时文件 `NAME.tmp`, 然后重命名
+    ///
+    /// 残留的临时文件先删除, 然后使用 `O_EXCL` 创建, 不会写入别的文件.
+    pub fn write_file(&self, name: &str, data: &[u8]) -> PmbsResult<()> {
+        let tmp = format!("{}.tmp", name);
+        let path = self.path.join(&tmp);
+        self.remove_file(&tmp)?;
+        let flags =
+            OFlags::WRONLY | OFlags::CREATE | OFlags::EXCL | OFlags::NOFOLLOW | OFlags::CLOEXEC;
+        let fd = openat(&self.fd, tmp.as_str(), flags, Mode::from_raw_mode(0o644))
+            .map_err(|e| at_err(&path, e))?;
+        File::from(fd)
+            .write_all(data)
+            .map_err(PmbsError::io(&path))?;
+        self.rename(&tmp, self, name)
+    }
+
+    /// 创建 (替换) 符号链接 `name -> target`: 先创建临时符号链接 `tmp`, 然后重命名
+    pub fn write_symlink(&self, target: &Path, name: &str, tmp: &str) -> PmbsResult<()> {
+        symlinkat(target, &self.fd, tmp)
+            .map_err(|e| PmbsError::io(&self.path.join(tmp))(e.into()))?;
+        self.rename(tmp, self, name)
+    }
+
+    /// 删除目录中的文件或符号链接 (不跟随符号链接), 不存在时返回 false
+    pub fn remove_file(&self, name: &str) -> PmbsResult<bool> {
+        match unlinkat(&self.fd, name, AtFlags::empty()) {
+            Ok(()) => Ok(true),
+            Err(Errno::NOENT) => Ok(false),
+            Err(e) => Err(PmbsError::io(&self.path.join(name))(e.into())),
+        }
+    }
+
+    /// 重命名 (移动) 到目录 `to` 中 (比如移动快照到别的年目录)
+    pub fn rename(&self, name: &str, to: &Self, to_name: &str) -> PmbsResult<()> {
+        renameat(&self.fd, name, &to.fd, to_name)
+            .map_err(|e| PmbsError::io(&to.path.join(to_name))(e.into()))
+    }
+}
+
+/// `*at` 系统调用的错误: `O_NOFOLLOW` 遇到符号链接 (ELOOP) 是安全错误
+fn at_err(path: &Path, e: Errno) -> PmbsError {
+    match e {
+        Errno::LOOP => {
+            PmbsError::Safety(format!("symlink not allowed  {}", path.to_string_lossy()))
+        }
+        e => PmbsError::io(path)(e.into()),
+    }
+}
+
+/// 检查: 是普通文件, 没有别的硬链接 (比如指向 /etc/shadow)
+fn check_file(path: &Path, m: &Metadata) -> PmbsResult<()> {
+    if !m.is_file() {
+        return Err(PmbsError::Safety(format!(
+            "not file  {}",
+            path.to_string_lossy()
+        )));
+    }
+    if m.nlink() > 1 {
+        return Err(PmbsError::Safety(format!(
+            "hard link not allowed  {}",
+            path.to_string_lossy()
+        )));
+    }
+    Ok(())
+}
+
+/// 检查: 是目录, 不是符号链接
+fn check_dir(path: &Path, m: &Metadata) -> PmbsResult<()> {
+    if m.file_type().is_symlink() {
+        return Err(PmbsError::Safety(format!(
+            "symlink not allowed  {}",
+            path.to_string_lossy()
+        )));
+    }
+    if !m.is_dir() {
+        return Err(PmbsError::Safety(format!(
+            "not dir  {}",
+            path.to_string_lossy()
+        )));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        fs::{create_dir_all, remove_dir_all},
+        os::unix::fs::symlink,
+    };
+
+    use super::*;
+    use crate::test_util::TmpDir;
+
+    #[test]
+    fn nofollow() {
+        let tmp = TmpDir::new("secure");
+        let other = tmp.join("other");
+        create_dir_all(other.join("2025/1756392923")).unwrap();
+
+        // 创建 .pmbs, 年目录
+        let dir = tmp.join("home/.pmbs");
+        let pmbs = SafeDir::open_pmbs(&dir, true).unwrap();
+        assert!(pmbs.open_dir("2024", false).unwrap().is_none());
+        let y = pmbs.open_dir("2024", true).unwrap().unwrap();
+        y.id.check_same(&dir.join("2024")).unwrap();
+        assert_eq!(y.stat_dir("1756392923").unwrap(), None);
+
+        // 符号链接: 年目录, 快照
+        symlink(other.join("2025"), dir.join("2025")).unwrap();
+        assert!(matches!(
+            pmbs.open_dir("2025", false),
+            Err(PmbsError::Safety(_))
+        ));
+        symlink(other.join("2025/1756392923"), dir.join("2024/1756392923")).unwrap();
+        assert!(matches!(
+            y.stat_dir("1756392923"),
+            Err(PmbsError::Safety(_))
+        ));
+
+        // 年目录被替换
+        remove_dir_all(dir.join("2024")).unwrap();
+        create_dir_all(dir.join("2024")).unwrap();
+        assert!(matches!(
+            y.id.check_same(&dir.join("2024")),
+            Err(PmbsError::Safety(_))
+        ));
+
+        // .pmbs 是符号链接
+        let link = tmp.join("link/.pmbs");
+        create_dir_all(tmp.join("link")).unwrap();
+        symlink(&other, &link).unwrap();
+        assert!(matches!(
+            SafeDir::open_pmbs(&link, true),
+            Err(PmbsError::Safety(_))
+        ));
+        // 之前的各级可以是 (当前用户的) 符号链接
+        symlink(tmp.join("home"), tmp.join("home2")).unwrap();
+        SafeDir::open_pmbs(&tmp.join("home2/.pmbs"), false).unwrap();
+    }
+}
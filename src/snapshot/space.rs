@@ -0,0 +1,91 @@
+//! 检查剩余存储空间
+use log::{debug, error};
+use serde::Serialize;
+
+use crate::config::PmbsConfigSpace;
+
+/// 文件系统存储空间 (字节)
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct Space {
+    /// 总空间
+    pub total: u64,
+    /// 剩余空间 (普通用户可用)
+    pub free: u64,
+}
+
+impl Space {
+    /// 剩余空间百分比
+    pub fn free_percent(&self) -> f64 {
+        if self.total > 0 {
+            (self.free as f64) * 100.0 / (self.total as f64)
+        } else {
+            0.0
+        }
+    }
+
+    /// 检查剩余空间是否低于限制
+    pub fn is_low(&self, c: &PmbsConfigSpace) -> bool {
+        self.is_below(c.min_free_bytes, c.min_free_percent)
+    }
+
+    /// 检查剩余空间是否低于 字节数/百分比
+    pub fn is_below(&self, min_free_bytes: Option<u64>, min_free_percent: Option<u32>) -> bool {
+        if let Some(b) = min_free_bytes
+            && self.free < b
+        {
+            return true;
+        }
+        if let Some(p) = min_free_percent
+            && self.free_percent() < (p as f64)
+        {
+            return true;
+        }
+        false
+    }
+}
+
+/// 获取指定路径所在文件系统的存储空间 (statvfs)
+pub fn get_space(path: &str) -> Option<Space> {
+    match rustix::fs::statvfs(path) {
+        Ok(s) => {
+            let space = Space {
+                total: s.f_blocks * s.f_frsize,
+                free: s.f_bavail * s.f_frsize,
+            };
+            debug!("space {}  {:?}", path, space);
+            Some(space)
+        }
+        Err(e) => {
+            error!("can not statvfs {}  {:?}", path, e);
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::config::SpacePolicy;
+
+    fn rule(min_free_bytes: Option<u64>, min_free_percent: Option<u32>) -> PmbsConfigSpace {
+        PmbsConfigSpace {
+            min_free_bytes,
+            min_free_percent,
+            policy: SpacePolicy::Warn,
+        }
+    }
+
+    #[test]
+    fn space_is_low() {
+        let s = Space {
+            total: 1000,
+            free: 100,
+        };
+        assert!(!s.is_low(&rule(None, None)));
+        assert!(!s.is_low(&rule(Some(100), None)));
+        assert!(s.is_low(&rule(Some(101), None)));
+        assert!(!s.is_low(&rule(None, Some(10))));
+        assert!(s.is_low(&rule(None, Some(11))));
+        assert!(s.is_low(&rule(Some(1), Some(20))));
+    }
+}
@@ -4,10 +4,19 @@ use std::time::SystemTime;
 use chrono::{DateTime, Datelike, Local, Utc, format::SecondsFormat};
 
 /// subvol 快照保存目录 /.pmbs/2025/T
-pub const DIR_PMBS: &'static str = ".pmbs";
+pub const DIR_PMBS: &str = ".pmbs";
 
 /// 最新快照的符号链接 /.pmbs/latest -> 2025/T
-pub const SYMLINK_LATEST: &'static str = "latest";
+pub const SYMLINK_LATEST: &str = "latest";
+
+/// 快照元数据目录 /.pmbs/meta/T.json
+pub const DIR_META: &str = "meta";
+
+/// 快照路径中的时间戳, 接受 `2025/1756392923`, `1756392923` 或者完整路径
+pub fn parse_snapshot_t(path: &str) -> Option<u64> {
+    let t = path.trim_end_matches('/').rsplit('/').next()?;
+    t.parse().ok()
+}
 
 /// 获取当前时间戳 (UNIX_EPOCH 开始的秒数)
 pub fn get_t() -> u64 {
@@ -0,0 +1,84 @@
+//! 检查 subvol 是否有变化 (btrfs generation)
+use std::path::Path;
+
+use log::{debug, info};
+
+use crate::{
+    backend::get_backend,
+    clean::ls_snapshot,
+    config::{ConfigEnv, PmbsConfig},
+};
+
+/// 检查是否跳过本次快照: 在 latest 快照之后, subvol 没有变化
+///
+/// 创建快照时, 快照的 `Gen at creation` 就是创建快照的 transid,
+/// 此后 subvol 的任何修改, 都会使 subvol 的 `Generation` 大于这个值.
+pub fn check_unchanged(c: &ConfigEnv, config: &PmbsConfig, t: u64) -> bool {
+    let subvol = &config.subvol;
+    let latest = match ls_snapshot(&config.dir_pmbs())
+        .unwrap_or_default()
+        .into_iter()
+        .find(|x| x.latest)
+    {
+        Some(latest) => latest,
+        None => {
+            debug!("no latest snapshot  {}", subvol);
+            return false;
+        }
+    };
+    // 强制快照
+    if t >= (latest.t + config.heartbeat_s) {
+        debug!("heartbeat  {}  latest = {}", subvol, latest.path);
+        return false;
+    }
+
+    let b = get_backend(c);
+    let (src, snap) = match (b.show(Path::new(subvol)), b.show(&latest.p)) {
+        (Ok(src), Ok(snap)) => (src, snap),
+        // 无法获取 generation, 创建快照更优先
+        _ => {
+            return false;
+        }
+    };
+    if src.generation <= snap.gen_at_creation {
+        info!(
+            "skip snapshot (unchanged)  {}  generation {} <= {}  latest = {}",
+            subvol, src.generation, snap.gen_at_creation, latest.path
+        );
+        true
+    } else {
+        false
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::fs::{create_dir_all, write};
+
+    use super::*;
+    use crate::{snapshot::make_snapshot, test_util::TmpDir};
+
+    /// skip_unchanged: subvol 没有变化时, 跳过快照
+    #[test]
+    fn skip_unchanged() {
+        let tmp = TmpDir::new("unchanged");
+        let subvol = tmp.join("subvol");
+        create_dir_all(&subvol).unwrap();
+        write(subvol.join("a.txt"), "1").unwrap();
+
+        let c = tmp.env();
+        let mut config = PmbsConfig::new_subvol(&subvol.to_string_lossy());
+        config.snapshot_dir = Some(tmp.join(".pmbs-store/home").to_string_lossy().to_string());
+        config.skip_unchanged = true;
+        config.heartbeat_s = 3600;
+        let dir = config.dir_pmbs();
+        make_snapshot(&c, &config, &Default::default()).unwrap();
+        make_snapshot(&c, &config, &Default::default()).unwrap();
+        assert_eq!(ls_snapshot(&dir).unwrap().len(), 1);
+
+        // 修改之后, 创建快照
+        write(subvol.join("b.txt"), "2").unwrap();
+        make_snapshot(&c, &config, &Default::default()).unwrap();
+        assert_eq!(ls_snapshot(&dir).unwrap().len(), 2);
+    }
+}
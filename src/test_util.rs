@@ -0,0 +1,48 @@
+//! 测试使用的临时目录, 以及 dir 后端的环境变量配置
+use std::{
+    env,
+    fs::{create_dir_all, remove_dir_all},
+    ops::Deref,
+    path::{Path, PathBuf},
+};
+
+use crate::{backend::BACKEND_DIR, config::ConfigEnv};
+
+/// 临时目录 (`pmbs-test-NAME-PID`), drop 时删除
+pub struct TmpDir(PathBuf);
+
+impl TmpDir {
+    /// 创建空的临时目录 (删除之前残留的)
+    pub fn new(name: &str) -> Self {
+        let p = env::temp_dir().join(format!("pmbs-test-{}-{}", name, std::process::id()));
+        let _ = remove_dir_all(&p);
+        create_dir_all(&p).unwrap();
+        Self(p)
+    }
+
+    /// 环境变量配置: 配置和日志保存在临时目录中, 使用 dir 后端
+    pub fn env(&self) -> ConfigEnv {
+        ConfigEnv {
+            dir_etc: self.join("etc").to_string_lossy().to_string(),
+            dir_log: self.join("log").to_string_lossy().to_string(),
+            bin_btrfs: "false".into(),
+            bin_ionice: String::new(),
+            backend: BACKEND_DIR.into(),
+            output: "text".into(),
+        }
+    }
+}
+
+impl Deref for TmpDir {
+    type Target = Path;
+
+    fn deref(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl Drop for TmpDir {
+    fn drop(&mut self) {
+        let _ = remove_dir_all(&self.0);
+    }
+}